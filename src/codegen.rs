@@ -1,71 +1,167 @@
 
 
+use inkwell::AddressSpace;
 use inkwell::AddressSpace::Global;
 use inkwell::execution_engine::ExecutionEngine;
 use inkwell::module::Linkage;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::module::Module;
-use inkwell::values::{BasicMetadataValueEnum, IntValue};
-use inkwell::{OptimizationLevel};
+use inkwell::types::{BasicType, BasicTypeEnum, FunctionType, IntType};
+use inkwell::basic_block::BasicBlock;
+use inkwell::values::{BasicMetadataValueEnum, FloatValue, FunctionValue, IntValue, PhiValue, PointerValue};
+use inkwell::{IntPredicate, OptimizationLevel};
 use inkwell::targets::{
     CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
 };
-use crate::parser::Expr;
+use logos::Span;
+use crate::diagnostics::SourceMap;
+use crate::error::CodegenError;
+use crate::parser::{BinaryOp, Expr, FloatWord, UnaryOp};
+
+// How many cells the runtime data stack has room for, and how many floats the float stack
+// has room for.
+const STACK_CELLS: u32 = 1024;
+
+// The exit code of a program stopped by a runtime error, following on from the exit codes
+// of the compiler itself in main.rs.
+const EXIT_RUNTIME_ERROR: u64 = 4;
+
+// The errno libc sets when a number it reads does not fit, which is the same on Linux and macOS.
+const ERANGE: u64 = 34;
+
+/// What `+`, `-` and `*` do when their result does not fit in a cell.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OverflowMode {
+    // Stop the program with an error pointing at the operator.
+    Checked,
+    // Wrap around in two's complement.
+    Wrapping,
+    // Clamp to the largest or smallest value a cell holds.
+    Saturating
+}
+
+impl std::str::FromStr for OverflowMode {
+    type Err = ();
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "checked" => Ok(OverflowMode::Checked),
+            "wrapping" => Ok(OverflowMode::Wrapping),
+            "saturating" => Ok(OverflowMode::Saturating),
+            _ => Err(())
+        }
+    }
+}
 
 pub struct Compiler<'a, 'ctx> {
     pub context: &'ctx Context,
     pub builder: &'a Builder<'ctx>,
     pub module: &'a Module<'ctx>,
     pub variables: Vec<IntValue<'ctx>>,
+    pub loop_indices: Vec<IntValue<'ctx>>,
+    pub memory_cells: u32,
+    pub overflow: OverflowMode,
+    // How many bits a cell has: 32, 64 or 128.
+    pub cell_bits: u32,
+    // The files the program came from, so runtime errors can point into them.
+    pub sources: &'a SourceMap<'a>,
     pub execution_engine: &'a ExecutionEngine<'ctx>,
     pub printf_defined: bool,
-    pub string_count: u16,
-    pub print_stack_count: u16
+    pub string_count: u16
 }
 
 impl<'a, 'ctx> Compiler<'a, 'ctx> {
 
-    pub fn compile_expr(&mut self, expr: &Expr) -> Result<IntValue<'ctx>, &'static str> {
+    pub fn compile_expr(&mut self, expr: &Expr) -> Result<IntValue<'ctx>, CodegenError> {
         match &expr {
             Expr::Number(nb) => {
-                let return_val = self.context.i32_type().const_int(nb.n as u64, true);
+                let return_val = self.cell_constant(nb.n);
                 self.variables.push(return_val.clone());
                 Ok(return_val)
             },
-            Expr::Binary { op, ref left, ref right } => {
-                let lhs = self.compile_expr(left)?;
-                let rhs = self.compile_expr(right)?;
-                self.variables.pop();
-                self.variables.pop();
-                match op {
-                    '+' => {
-                        let return_val = self.builder.build_int_add(lhs, rhs, "anAdd");
-                        self.variables.push(return_val.clone());
-                        Ok(return_val)
-                    },
-                    '-' => {
-                        let return_val = self.builder.build_int_sub(lhs, rhs, "aSub");
-                        self.variables.push(return_val.clone());
-                        Ok(return_val)
-                    },
-                    '*' => {
-                        let return_val = self.builder.build_int_mul(lhs, rhs, "aMult");
-                        self.variables.push(return_val.clone());
-                        Ok(return_val)
+            Expr::Float(x) => {
+                let value = self.context.f64_type().const_float(*x);
+                self.push_float(value);
+                Ok(self.cell_type().const_zero())
+            }
+            Expr::FloatWord(word) => {
+                self.compile_float_word(*word);
+                Ok(self.cell_type().const_zero())
+            }
+            Expr::CellToFloat => {
+                let value = self.pop_value();
+                let float = self.builder.build_signed_int_to_float(value, self.context.f64_type(), "toFloat");
+                self.push_float(float);
+                Ok(value)
+            }
+            Expr::FloatToCell => {
+                let float = self.pop_float();
+                let value = self.builder.build_float_to_signed_int(float, self.cell_type(), "toCell");
+                self.variables.push(value);
+                Ok(value)
+            }
+            Expr::Binary { op, ref left, ref right, span } => {
+                // The operands are whatever the two sides leave on top of the stack, which
+                // is not necessarily the value they return once stack words are involved.
+                self.compile_expr(left)?;
+                self.compile_expr(right)?;
+                let rhs = self.pop_value();
+                let lhs = self.pop_value();
+                if matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+                    self.check_division(lhs, rhs, span);
+                }
+                let return_val = match op {
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul => self.build_arithmetic(*op, lhs, rhs, span),
+                    BinaryOp::Div => self.builder.build_int_signed_div(lhs, rhs, "aDiv"),
+                    BinaryOp::Equal => self.compare(IntPredicate::EQ, lhs, rhs),
+                    BinaryOp::NotEqual => self.compare(IntPredicate::NE, lhs, rhs),
+                    BinaryOp::Less => self.compare(IntPredicate::SLT, lhs, rhs),
+                    BinaryOp::Greater => self.compare(IntPredicate::SGT, lhs, rhs),
+                    BinaryOp::LessEqual => self.compare(IntPredicate::SLE, lhs, rhs),
+                    BinaryOp::GreaterEqual => self.compare(IntPredicate::SGE, lhs, rhs),
+                    BinaryOp::And => self.builder.build_and(lhs, rhs, "anAnd"),
+                    BinaryOp::Or => self.builder.build_or(lhs, rhs, "anOr"),
+                    BinaryOp::Xor => self.builder.build_xor(lhs, rhs, "aXor"),
+                    BinaryOp::Mod => self.builder.build_int_signed_rem(lhs, rhs, "aMod"),
+                    BinaryOp::Min => {
+                        let less = self.builder.build_int_compare(IntPredicate::SLT, lhs, rhs, "less");
+                        self.builder.build_select(less, lhs, rhs, "aMin").into_int_value()
                     },
-                    '/' => {
-                        let return_val = self.builder.build_int_signed_div(lhs, rhs, "aDiv");
-                        self.variables.push(return_val.clone());
-                        Ok(return_val)
+                    BinaryOp::Max => {
+                        let greater = self.builder.build_int_compare(IntPredicate::SGT, lhs, rhs, "greater");
+                        self.builder.build_select(greater, lhs, rhs, "aMax").into_int_value()
                     },
-                    _ => Err("Invalid operator. Check parser did not parse incorrectly.")
-                }
+                    BinaryOp::LShift => self.builder.build_left_shift(lhs, rhs, "anLShift"),
+                    // RSHIFT is a logical shift in Forth, filling in zeros from the left.
+                    BinaryOp::RShift => self.builder.build_right_shift(lhs, rhs, false, "anRShift")
+                };
+                self.variables.push(return_val);
+                Ok(return_val)
+            }
+            Expr::Unary { op, ref operand, .. } => {
+                self.compile_expr(operand)?;
+                let value = self.pop_value();
+                let zero = self.cell_type().const_int(0, false);
+                let return_val = match op {
+                    UnaryOp::ZeroEqual => self.compare(IntPredicate::EQ, value, zero),
+                    UnaryOp::ZeroLess => self.compare(IntPredicate::SLT, value, zero),
+                    UnaryOp::ZeroGreater => self.compare(IntPredicate::SGT, value, zero),
+                    UnaryOp::Invert => self.builder.build_not(value, "anInvert"),
+                    UnaryOp::Negate => self.builder.build_int_neg(value, "aNegate"),
+                    UnaryOp::Abs => {
+                        let negative = self.builder.build_int_compare(IntPredicate::SLT, value, zero, "negative");
+                        let negated = self.builder.build_int_neg(value, "negated");
+                        self.builder.build_select(negative, negated, value, "anAbs").into_int_value()
+                    }
+                };
+                self.variables.push(return_val);
+                Ok(return_val)
             }
             Expr::Call { ref other, ref actual } => {
-                let some_expr = self.compile_expr(other);
-                let _ = self.compile_expr(actual);
-                Ok(some_expr.unwrap())
+                let some_expr = self.compile_expr(other)?;
+                self.compile_expr(actual)?;
+                Ok(some_expr)
             }
             Expr::StringPrint(str) => {
                 if !self.printf_defined {
@@ -75,36 +171,1176 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
                 let name_of_string = "string".to_string() + &self.string_count.to_string();
                 let the_string = self.builder.build_global_string_ptr(str.as_str(), name_of_string.as_str());
                 self.string_count += 1;
+                // The string is passed as an argument so a % in it is printed as written.
+                let format_string = self.builder.build_global_string_ptr("%s", "string_format");
                 let mut arguments: Vec<BasicMetadataValueEnum> = vec![];
+                arguments.push(format_string.as_pointer_value().into());
                 arguments.push(the_string.as_pointer_value().into());
                 self.builder.build_call(self.module.get_function("printf").unwrap(), &arguments, "printf");
-                Ok(self.context.i32_type().const_int(0, false))
+                Ok(self.cell_type().const_int(0, false))
             }
             Expr::PrintStack => {
                 if !self.printf_defined {
                     self.define_printf();
                     self.printf_defined = true
                 }
-                let mut arguments: Vec<BasicMetadataValueEnum> = vec![];
-                let mut format_string: String = "".to_string();
-                let name_of_string = "print_stack".to_string() + &self.print_stack_count.to_string();
-                for _ in self.variables.clone() {
-                    format_string = format_string + "%d ";
+                // The depth comes first, as in `<2> 3 4`, counting the values in memory too.
+                let i32_type = self.context.i32_type();
+                let held = i32_type.const_int(self.variables.len() as u64, false);
+                let depth = match self.module.get_global("meido_sp") {
+                    Some(global) => {
+                        let in_memory = self.builder.build_load(global.as_pointer_value(), "depth").into_int_value();
+                        self.builder.build_int_add(in_memory, held, "depth")
+                    },
+                    None => held
+                };
+                let format_string = self.builder.build_global_string_ptr("<%d> ", "depth_format");
+                let arguments: Vec<BasicMetadataValueEnum> = vec![format_string.as_pointer_value().into(), depth.into()];
+                self.builder.build_call(self.module.get_function("printf").unwrap(), &arguments, "printf");
+                self.print_runtime_stack();
+                for value in self.variables.clone() {
+                    self.print_cell(value);
                 }
-                let the_string = self.builder.build_global_string_ptr(format_string.as_str(), name_of_string.as_str());
-                self.print_stack_count += 1;
-                arguments.push(the_string.as_pointer_value().into());
-                for var in self.variables.clone() {
-                    arguments.push(var.into());
+                Ok(self.cell_type().const_int(0, false))
+            }
+            Expr::PrintTop => {
+                if !self.printf_defined {
+                    self.define_printf();
+                    self.printf_defined = true
                 }
-
+                let value = self.pop_value();
+                self.print_cell(value);
+                Ok(value)
+            }
+            Expr::Emit => {
+                if !self.printf_defined {
+                    self.define_printf();
+                    self.printf_defined = true
+                }
+                let value = self.pop_value();
+                let character = self.builder.build_int_cast(value, self.context.i32_type(), "character");
+                let format_string = self.builder.build_global_string_ptr("%c", "emit_format");
+                let arguments: Vec<BasicMetadataValueEnum> = vec![format_string.as_pointer_value().into(), character.into()];
                 self.builder.build_call(self.module.get_function("printf").unwrap(), &arguments, "printf");
-                Ok(self.context.i32_type().const_int(0, false))
+                Ok(value)
+            }
+            Expr::Key => {
+                let getchar = self.libc_function("getchar", self.context.i32_type().fn_type(&[], false));
+                let byte = self.builder.build_call(getchar, &[], "getchar")
+                    .try_as_basic_value()
+                    .left()
+                    .expect("getchar returns the byte it read.")
+                    .into_int_value();
+                let value = self.builder.build_int_cast(byte, self.cell_type(), "key");
+                self.variables.push(value);
+                Ok(value)
+            }
+            Expr::Accept { ref span } => {
+                let count = self.pop_value();
+                let address = self.pop_value();
+                self.check_memory_range(address, count, span);
+                let kept = self.builder.build_call(self.accept_helper(), &[address.into(), count.into()], "accept")
+                    .try_as_basic_value()
+                    .left()
+                    .expect("meido_accept returns how many characters it kept.")
+                    .into_int_value();
+                self.variables.push(kept);
+                Ok(kept)
+            }
+            Expr::ReadNumber => {
+                let (number, flag) = self.read_number();
+                self.push_values(&[number, flag]);
+                Ok(number)
+            }
+            Expr::ArgCount => {
+                let argc = self.module.get_global("meido_argc").expect("build_main keeps argc in a global.");
+                let argc = self.builder.build_load(argc.as_pointer_value(), "argc").into_int_value();
+                let value = self.builder.build_int_cast(argc, self.cell_type(), "argCount");
+                self.variables.push(value);
+                Ok(value)
+            }
+            Expr::Argument { ref span } => {
+                let count = self.pop_value();
+                let address = self.pop_value();
+                let n = self.pop_value();
+                self.check_memory_range(address, count, span);
+                let argument = self.argument(n);
+                let kept = self.copy_string(argument, address, count);
+                self.variables.push(kept);
+                Ok(kept)
+            }
+            Expr::ArgumentNumber => {
+                let n = self.pop_value();
+                let argument = self.argument(n);
+                let (number, flag) = self.parse_number(argument);
+                self.push_values(&[number, flag]);
+                Ok(number)
+            }
+            Expr::Environment { ref name, ref span } => {
+                let count = self.pop_value();
+                let address = self.pop_value();
+                self.check_memory_range(address, count, span);
+                let string_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+                let getenv = self.libc_function("getenv", string_type.fn_type(&[string_type.into()], false));
+                let name = self.builder.build_global_string_ptr(name, "env_name");
+                let value = self.builder.build_call(getenv, &[name.as_pointer_value().into()], "getenv")
+                    .try_as_basic_value()
+                    .left()
+                    .expect("getenv returns the value of the variable.")
+                    .into_pointer_value();
+                let kept = self.copy_string(value, address, count);
+                self.variables.push(kept);
+                Ok(kept)
+            }
+            Expr::NewLine => {
+                if !self.printf_defined {
+                    self.define_printf();
+                    self.printf_defined = true
+                }
+                let newline = self.builder.build_global_string_ptr("\n", "newline");
+                self.builder.build_call(self.module.get_function("printf").unwrap(), &[newline.as_pointer_value().into()], "printf");
+                Ok(self.cell_type().const_zero())
             }
             Expr::ProgramEnd => {
-                Ok(self.context.i32_type().const_int(0, false))
+                Ok(self.cell_type().const_int(0, false))
+            }
+            Expr::Bye => {
+                let code = self.pop_value();
+                let exit_type = self.context.void_type().fn_type(&[self.context.i32_type().into()], false);
+                let exit = self.libc_function("exit", exit_type);
+                let code = self.builder.build_int_cast(code, self.context.i32_type(), "exitCode");
+                self.builder.build_call(exit, &[code.into()], "exit");
+                self.builder.build_unreachable();
+                // Whatever follows BYE is never run, but it still needs a block to go in.
+                let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+                let after = self.context.append_basic_block(function, "afterBye");
+                self.builder.position_at_end(after);
+                Ok(code)
+            }
+            Expr::Dup => {
+                let a = self.pop_value();
+                self.push_values(&[a, a]);
+                Ok(a)
+            }
+            Expr::Drop => {
+                Ok(self.pop_value())
+            }
+            Expr::Swap => {
+                let b = self.pop_value();
+                let a = self.pop_value();
+                self.push_values(&[b, a]);
+                Ok(a)
+            }
+            Expr::Over => {
+                let b = self.pop_value();
+                let a = self.pop_value();
+                self.push_values(&[a, b, a]);
+                Ok(a)
+            }
+            Expr::Rot => {
+                let c = self.pop_value();
+                let b = self.pop_value();
+                let a = self.pop_value();
+                self.push_values(&[b, c, a]);
+                Ok(a)
+            }
+            Expr::Nip => {
+                let b = self.pop_value();
+                self.pop_value();
+                self.push_values(&[b]);
+                Ok(b)
+            }
+            Expr::Tuck => {
+                let b = self.pop_value();
+                let a = self.pop_value();
+                self.push_values(&[b, a, b]);
+                Ok(b)
+            }
+            Expr::DivMod { span } => {
+                let divisor = self.pop_value();
+                let dividend = self.pop_value();
+                self.check_division(dividend, divisor, span);
+                let remainder = self.builder.build_int_signed_rem(dividend, divisor, "aMod");
+                let quotient = self.builder.build_int_signed_div(dividend, divisor, "aDiv");
+                self.push_values(&[remainder, quotient]);
+                Ok(quotient)
+            }
+            Expr::If { ref then_branch, ref else_branch, dynamic, .. } => {
+                let flag = self.pop_value();
+                let zero = self.cell_type().const_int(0, false);
+                let condition = self.builder.build_int_compare(IntPredicate::NE, flag, zero, "ifFlag");
+                let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+                let then_block = self.context.append_basic_block(function, "then");
+                let else_block = self.context.append_basic_block(function, "else");
+                let merge_block = self.context.append_basic_block(function, "endIf");
+                self.builder.build_conditional_branch(condition, then_block, else_block);
+
+                // Each branch starts from the stack as it was before IF. Values that a
+                // branch pushes or replaces are merged with phi nodes after THEN.
+                let before = self.variables.clone();
+                self.builder.position_at_end(then_block);
+                self.compile_block(then_branch)?;
+                let then_end = self.builder.get_insert_block().unwrap();
+                let then_stack = std::mem::replace(&mut self.variables, before);
+
+                self.builder.position_at_end(else_block);
+                self.compile_block(else_branch)?;
+                let else_end = self.builder.get_insert_block().unwrap();
+                let else_stack = std::mem::take(&mut self.variables);
+
+                // A branch that pops values from memory keeps fewer of them in SSA form,
+                // so both are settled to the same depth before they are merged. When the
+                // depth after THEN depends on the branch taken, everything goes to memory.
+                let depth = if *dynamic { 0 } else { then_stack.len().min(else_stack.len()) };
+                let then_stack = self.settle_branch(then_end, then_stack, depth, merge_block);
+                let else_stack = self.settle_branch(else_end, else_stack, depth, merge_block);
+                self.builder.position_at_end(merge_block);
+                for (then_value, else_value) in then_stack.into_iter().zip(else_stack) {
+                    if then_value == else_value {
+                        self.variables.push(then_value);
+                    } else {
+                        let phi = self.builder.build_phi(self.cell_type(), "ifStack");
+                        phi.add_incoming(&[(&then_value, then_end), (&else_value, else_end)]);
+                        self.variables.push(phi.as_basic_value().into_int_value());
+                    }
+                }
+                Ok(zero)
+            }
+            Expr::BeginUntil { ref body, dynamic, .. } => {
+                // A loop that changes the depth of the stack keeps all of it in memory, so
+                // that every trip round it starts from the same SSA values: none.
+                if *dynamic {
+                    self.settle(0);
+                }
+                let (header, phis) = self.build_loop_header("beginUntil");
+                self.compile_block(body)?;
+                let flag = self.pop_value();
+                self.close_loop_header(&phis);
+                let zero = self.cell_type().const_int(0, false);
+                let condition = self.builder.build_int_compare(IntPredicate::NE, flag, zero, "untilFlag");
+                let function = header.get_parent().unwrap();
+                let exit_block = self.context.append_basic_block(function, "endUntil");
+                self.builder.build_conditional_branch(condition, exit_block, header);
+                self.builder.position_at_end(exit_block);
+                Ok(zero)
+            }
+            Expr::BeginWhile { ref condition, ref body, dynamic, .. } => {
+                if *dynamic {
+                    self.settle(0);
+                }
+                let (header, phis) = self.build_loop_header("beginWhile");
+                self.compile_block(condition)?;
+                let flag = self.pop_value();
+                let zero = self.cell_type().const_int(0, false);
+                let keep_going = self.builder.build_int_compare(IntPredicate::NE, flag, zero, "whileFlag");
+                let function = header.get_parent().unwrap();
+                let body_block = self.context.append_basic_block(function, "whileBody");
+                let exit_block = self.context.append_basic_block(function, "endWhile");
+                self.builder.build_conditional_branch(keep_going, body_block, exit_block);
+
+                // The loop is left from the condition, so the stack after REPEAT is the
+                // stack the condition ended with.
+                let after_condition = self.variables.clone();
+                self.builder.position_at_end(body_block);
+                self.compile_block(body)?;
+                self.close_loop_header(&phis);
+                self.builder.build_unconditional_branch(header);
+                self.builder.position_at_end(exit_block);
+                self.variables = after_condition;
+                Ok(zero)
+            }
+            Expr::DoLoop { ref body, dynamic, .. } => {
+                let start = self.pop_value();
+                let limit = self.pop_value();
+                if *dynamic {
+                    self.settle(0);
+                }
+                let preheader = self.builder.get_insert_block().unwrap();
+                let (header, phis) = self.build_loop_header("doLoop");
+                let index = self.builder.build_phi(self.cell_type(), "loopIndex");
+                index.add_incoming(&[(&start, preheader)]);
+                let index_value = index.as_basic_value().into_int_value();
+
+                self.loop_indices.push(index_value);
+                self.compile_block(body)?;
+                self.loop_indices.pop();
+
+                let one = self.cell_type().const_int(1, false);
+                let next = self.builder.build_int_add(index_value, one, "nextIndex");
+                let keep_going = self.builder.build_int_compare(IntPredicate::SLT, next, limit, "loopFlag");
+                self.close_loop_header(&phis);
+                index.add_incoming(&[(&next, self.builder.get_insert_block().unwrap())]);
+                let function = header.get_parent().unwrap();
+                let exit_block = self.context.append_basic_block(function, "endLoop");
+                self.builder.build_conditional_branch(keep_going, header, exit_block);
+                self.builder.position_at_end(exit_block);
+                Ok(index_value)
+            }
+            Expr::Definition { ref name, ref body, .. } => {
+                self.compile_definition(name, body)?;
+                Ok(self.cell_type().const_int(0, false))
+            }
+            Expr::CallWord { ref name, .. } => {
+                let function = self.module.get_function(&word_symbol(name))
+                    .expect("The parser checks every word is defined before it is called.");
+                // Words take their inputs from memory, so everything still held in SSA
+                // values has to be written there first.
+                self.flush_values();
+                self.builder.build_call(function, &[], "callWord");
+                Ok(self.cell_type().const_int(0, false))
+            }
+            Expr::DeclareVariable { .. } => {
+                Ok(self.cell_type().const_int(0, false))
+            }
+            Expr::VariableAddress { cell, .. } => {
+                let address = self.cell_type().const_int(*cell as u64, false);
+                self.variables.push(address);
+                Ok(address)
+            }
+            Expr::Store { ref span } => {
+                let address = self.pop_value();
+                let value = self.pop_value();
+                let slot = self.memory_cell(address, span);
+                self.builder.build_store(slot, value);
+                Ok(value)
+            }
+            Expr::Fetch { ref span } => {
+                let address = self.pop_value();
+                let slot = self.memory_cell(address, span);
+                let value = self.builder.build_load(slot, "fetched").into_int_value();
+                self.variables.push(value);
+                Ok(value)
+            }
+            Expr::LoopIndex => {
+                let index = *self.loop_indices.last().expect("The parser only allows I inside DO … LOOP.");
+                self.variables.push(index);
+                Ok(index)
+            }
+            Expr::Stacked | Expr::Include { .. } => {
+                Ok(self.cell_type().const_int(0, false))
+            }
+        }
+    }
+
+    /// The float stack is always kept in memory, so every float word goes through
+    /// `meido_fpop` and `meido_fpush`.
+    fn compile_float_word(&mut self, word: FloatWord) {
+        match word {
+            FloatWord::Add | FloatWord::Sub | FloatWord::Mul | FloatWord::Div => {
+                let rhs = self.pop_float();
+                let lhs = self.pop_float();
+                let result = match word {
+                    FloatWord::Add => self.builder.build_float_add(lhs, rhs, "fAdd"),
+                    FloatWord::Sub => self.builder.build_float_sub(lhs, rhs, "fSub"),
+                    FloatWord::Mul => self.builder.build_float_mul(lhs, rhs, "fMult"),
+                    _ => self.builder.build_float_div(lhs, rhs, "fDiv")
+                };
+                self.push_float(result);
+            },
+            FloatWord::Negate => {
+                let value = self.pop_float();
+                self.push_float(self.builder.build_float_neg(value, "fNegate"));
+            },
+            FloatWord::Print => {
+                if !self.printf_defined {
+                    self.define_printf();
+                    self.printf_defined = true
+                }
+                let value = self.pop_float();
+                let format_string = self.builder.build_global_string_ptr("%g ", "float_format");
+                let arguments: Vec<BasicMetadataValueEnum> = vec![format_string.as_pointer_value().into(), value.into()];
+                self.builder.build_call(self.module.get_function("printf").unwrap(), &arguments, "printf");
+            },
+            FloatWord::Dup => {
+                let value = self.pop_float();
+                self.push_float(value);
+                self.push_float(value);
+            },
+            FloatWord::Drop => {
+                self.pop_float();
+            },
+            FloatWord::Swap => {
+                let b = self.pop_float();
+                let a = self.pop_float();
+                self.push_float(b);
+                self.push_float(a);
+            },
+            FloatWord::Over => {
+                let b = self.pop_float();
+                let a = self.pop_float();
+                self.push_float(a);
+                self.push_float(b);
+                self.push_float(a);
+            }
+        }
+    }
+
+    fn build_arithmetic(&self, op: BinaryOp, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>, span: &Span) -> IntValue<'ctx> {
+        let cell_type = self.cell_type();
+        let (name, intrinsic) = match op {
+            BinaryOp::Add => ("anAdd", "llvm.sadd"),
+            BinaryOp::Sub => ("aSub", "llvm.ssub"),
+            _ => ("aMult", "llvm.smul")
+        };
+        match self.overflow {
+            OverflowMode::Wrapping => match op {
+                BinaryOp::Add => self.builder.build_int_add(lhs, rhs, name),
+                BinaryOp::Sub => self.builder.build_int_sub(lhs, rhs, name),
+                _ => self.builder.build_int_mul(lhs, rhs, name)
+            },
+            OverflowMode::Saturating => {
+                // There is no saturating multiply as such, but a fixed point multiply
+                // with no fractional bits is one.
+                let mut arguments: Vec<BasicMetadataValueEnum> = vec![lhs.into(), rhs.into()];
+                let function = if op == BinaryOp::Mul {
+                    let i32_type = self.context.i32_type();
+                    arguments.push(i32_type.const_zero().into());
+                    let fn_type = cell_type.fn_type(&[cell_type.into(), cell_type.into(), i32_type.into()], false);
+                    self.intrinsic(&format!("llvm.smul.fix.sat.i{}", self.cell_bits), fn_type)
+                } else {
+                    let fn_type = cell_type.fn_type(&[cell_type.into(), cell_type.into()], false);
+                    self.intrinsic(&format!("{}.sat.i{}", intrinsic, self.cell_bits), fn_type)
+                };
+                self.builder.build_call(function, &arguments, name)
+                    .try_as_basic_value()
+                    .left()
+                    .expect("Saturating intrinsics return their result.")
+                    .into_int_value()
+            },
+            OverflowMode::Checked => {
+                let result_type = self.context.struct_type(&[cell_type.into(), self.context.bool_type().into()], false);
+                let fn_type = result_type.fn_type(&[cell_type.into(), cell_type.into()], false);
+                let function = self.intrinsic(&format!("{}.with.overflow.i{}", intrinsic, self.cell_bits), fn_type);
+                let result = self.builder.build_call(function, &[lhs.into(), rhs.into()], name)
+                    .try_as_basic_value()
+                    .left()
+                    .expect("Overflow intrinsics return their result.")
+                    .into_struct_value();
+                let value = self.builder.build_extract_value(result, 0, name).unwrap().into_int_value();
+                let overflowed = self.builder.build_extract_value(result, 1, "overflowed").unwrap().into_int_value();
+
+                let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+                let overflow = self.context.append_basic_block(function, "overflow");
+                let no_overflow = self.context.append_basic_block(function, "noOverflow");
+                self.builder.build_conditional_branch(overflowed, overflow, no_overflow);
+                self.builder.position_at_end(overflow);
+                self.build_trap(&self.located("Arithmetic overflow.", span));
+                self.builder.position_at_end(no_overflow);
+                value
+            }
+        }
+    }
+
+    fn cell_type(&self) -> IntType<'ctx> {
+        self.context.custom_width_int_type(self.cell_bits)
+    }
+
+    /// `const_int` only takes 64 bits, which is not enough for the widest cells, so the
+    /// constant is given as two words with the low one first.
+    fn cell_constant(&self, n: i128) -> IntValue<'ctx> {
+        self.cell_type().const_int_arbitrary_precision(&[n as u64, (n >> 64) as u64])
+    }
+
+    /// Declares a function from libc the first time it is used, as `define_printf` does
+    /// for printf.
+    fn libc_function(&self, name: &str, fn_type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| self.module.add_function(name, fn_type, Some(Linkage::External)))
+    }
+
+    /// `meido_accept` reads a line from stdin into the cells starting at an address, and
+    /// returns how many characters it kept. Characters past the count it was given are
+    /// read and dropped, so the next read starts on the next line.
+    fn accept_helper(&self) -> FunctionValue<'ctx> {
+        if let Some(function) = self.module.get_function("meido_accept") {
+            return function;
+        }
+        let cell_type = self.cell_type();
+        let i32_type = self.context.i32_type();
+        let fn_type = cell_type.fn_type(&[cell_type.into(), cell_type.into()], false);
+        let function = self.module.add_function("meido_accept", fn_type, Some(Linkage::Internal));
+        let getchar = self.libc_function("getchar", i32_type.fn_type(&[], false));
+        let resume = self.builder.get_insert_block();
+        let entry = self.context.append_basic_block(function, "entry");
+        let read = self.context.append_basic_block(function, "read");
+        let check_room = self.context.append_basic_block(function, "checkRoom");
+        let keep = self.context.append_basic_block(function, "keep");
+        let done = self.context.append_basic_block(function, "done");
+        let address = function.get_nth_param(0).unwrap().into_int_value();
+        let limit = function.get_nth_param(1).unwrap().into_int_value();
+
+        self.builder.position_at_end(entry);
+        self.builder.build_unconditional_branch(read);
+
+        self.builder.position_at_end(read);
+        let count = self.builder.build_phi(cell_type, "count");
+        let count_value = count.as_basic_value().into_int_value();
+        let byte = self.builder.build_call(getchar, &[], "getchar")
+            .try_as_basic_value()
+            .left()
+            .expect("getchar returns the byte it read.")
+            .into_int_value();
+        let at_end = self.builder.build_int_compare(IntPredicate::EQ, byte, i32_type.const_all_ones(), "atEnd");
+        let newline = self.builder.build_int_compare(IntPredicate::EQ, byte, i32_type.const_int('\n' as u64, false), "newline");
+        let finished = self.builder.build_or(at_end, newline, "finished");
+        self.builder.build_conditional_branch(finished, done, check_room);
+
+        self.builder.position_at_end(check_room);
+        let room = self.builder.build_int_compare(IntPredicate::SLT, count_value, limit, "room");
+        self.builder.build_conditional_branch(room, keep, read);
+
+        self.builder.position_at_end(keep);
+        let offset = self.builder.build_int_add(address, count_value, "offset");
+        let slot = self.memory_slot(offset);
+        self.builder.build_store(slot, self.builder.build_int_cast(byte, cell_type, "character"));
+        let next = self.builder.build_int_add(count_value, cell_type.const_int(1, false), "next");
+        self.builder.build_unconditional_branch(read);
+        count.add_incoming(&[(&cell_type.const_zero(), entry), (&count_value, check_room), (&next, keep)]);
+
+        self.builder.position_at_end(done);
+        self.builder.build_return(Some(&count_value));
+
+        if let Some(block) = resume {
+            self.builder.position_at_end(block);
+        }
+        function
+    }
+
+    /// Argument `n` of the program, or a null pointer when it was not given that many.
+    fn argument(&self, n: IntValue<'ctx>) -> PointerValue<'ctx> {
+        let cell_type = self.cell_type();
+        let argc = self.module.get_global("meido_argc").expect("build_main keeps argc in a global.");
+        let argv = self.module.get_global("meido_argv").expect("build_main keeps argv in a global.");
+        let argc = self.builder.build_load(argc.as_pointer_value(), "argc").into_int_value();
+        let argc = self.builder.build_int_cast(argc, cell_type, "argc");
+        let not_negative = self.builder.build_int_compare(IntPredicate::SGE, n, cell_type.const_zero(), "notNegative");
+        let given = self.builder.build_int_compare(IntPredicate::SLT, n, argc, "given");
+        let valid = self.builder.build_and(not_negative, given, "valid");
+        // argv[0] is read in place of a missing argument and then ignored.
+        let index = self.builder.build_select(valid, n, cell_type.const_zero(), "index").into_int_value();
+        let argv = self.builder.build_load(argv.as_pointer_value(), "argv").into_pointer_value();
+        let slot = unsafe { self.builder.build_in_bounds_gep(argv, &[index], "argument") };
+        let argument = self.builder.build_load(slot, "argument").into_pointer_value();
+        let missing = argument.get_type().const_null();
+        self.builder.build_select(valid, argument, missing, "argument").into_pointer_value()
+    }
+
+    /// Copies a C string into the cells starting at an address, one byte to a cell, and
+    /// gives how many it kept, or -1 for a null pointer. `meido_copy_string` stops at
+    /// the count it is given.
+    fn copy_string(&self, string: PointerValue<'ctx>, address: IntValue<'ctx>, count: IntValue<'ctx>) -> IntValue<'ctx> {
+        let function = self.module.get_function("meido_copy_string").unwrap_or_else(|| {
+            let cell_type = self.cell_type();
+            let fn_type = cell_type.fn_type(&[string.get_type().into(), cell_type.into(), cell_type.into()], false);
+            let function = self.module.add_function("meido_copy_string", fn_type, Some(Linkage::Internal));
+            let resume = self.builder.get_insert_block();
+            let entry = self.context.append_basic_block(function, "entry");
+            let missing = self.context.append_basic_block(function, "missing");
+            let copy = self.context.append_basic_block(function, "copy");
+            let keep = self.context.append_basic_block(function, "keep");
+            let done = self.context.append_basic_block(function, "done");
+            let source = function.get_nth_param(0).unwrap().into_pointer_value();
+            let address = function.get_nth_param(1).unwrap().into_int_value();
+            let limit = function.get_nth_param(2).unwrap().into_int_value();
+
+            self.builder.position_at_end(entry);
+            let is_null = self.builder.build_is_null(source, "isNull");
+            self.builder.build_conditional_branch(is_null, missing, copy);
+
+            self.builder.position_at_end(missing);
+            self.builder.build_return(Some(&cell_type.const_all_ones()));
+
+            self.builder.position_at_end(copy);
+            let count = self.builder.build_phi(cell_type, "count");
+            let count_value = count.as_basic_value().into_int_value();
+            let from = unsafe { self.builder.build_in_bounds_gep(source, &[count_value], "from") };
+            let byte = self.builder.build_load(from, "byte").into_int_value();
+            let at_end = self.builder.build_int_compare(IntPredicate::EQ, byte, self.context.i8_type().const_zero(), "atEnd");
+            let full = self.builder.build_int_compare(IntPredicate::SGE, count_value, limit, "full");
+            let finished = self.builder.build_or(at_end, full, "finished");
+            self.builder.build_conditional_branch(finished, done, keep);
+
+            self.builder.position_at_end(keep);
+            let offset = self.builder.build_int_add(address, count_value, "offset");
+            let slot = self.memory_slot(offset);
+            self.builder.build_store(slot, self.builder.build_int_z_extend(byte, cell_type, "character"));
+            let next = self.builder.build_int_add(count_value, cell_type.const_int(1, false), "next");
+            self.builder.build_unconditional_branch(copy);
+            count.add_incoming(&[(&cell_type.const_zero(), entry), (&next, keep)]);
+
+            self.builder.position_at_end(done);
+            self.builder.build_return(Some(&count_value));
+
+            if let Some(block) = resume {
+                self.builder.position_at_end(block);
+            }
+            function
+        });
+        self.builder.build_call(function, &[string.into(), address.into(), count.into()], "copyString")
+            .try_as_basic_value()
+            .left()
+            .expect("meido_copy_string returns how many characters it kept.")
+            .into_int_value()
+    }
+
+    /// Parses a C string as a whole number with strtoll, giving the number and a flag that
+    /// is true when all of the string spelled one. A null pointer, or a number that does not
+    /// fit in a cell, gives 0 and false.
+    fn parse_number(&self, string: PointerValue<'ctx>) -> (IntValue<'ctx>, IntValue<'ctx>) {
+        let cell_type = self.cell_type();
+        let i32_type = self.context.i32_type();
+        let i64_type = self.context.i64_type();
+        let string_type = string.get_type();
+        let end_type = string_type.ptr_type(AddressSpace::Generic);
+        let strtoll = self.libc_function("strtoll", i64_type.fn_type(&[string_type.into(), end_type.into(), i32_type.into()], false));
+        let end_ptr = self.module.get_global("meido_number_end").unwrap_or_else(|| {
+            let global = self.module.add_global(string_type, None, "meido_number_end");
+            global.set_linkage(Linkage::Internal);
+            global.set_initializer(&string_type.const_null());
+            global
+        });
+
+        let start = self.builder.get_insert_block().unwrap();
+        let function = start.get_parent().unwrap();
+        let parse = self.context.append_basic_block(function, "parseNumber");
+        let done = self.context.append_basic_block(function, "parsedNumber");
+        let is_null = self.builder.build_is_null(string, "isNull");
+        self.builder.build_conditional_branch(is_null, done, parse);
+
+        self.builder.position_at_end(parse);
+        let errno = self.errno();
+        self.builder.build_store(errno, i32_type.const_zero());
+        let arguments: Vec<BasicMetadataValueEnum> = vec![string.into(), end_ptr.as_pointer_value().into(), i32_type.const_int(10, false).into()];
+        let parsed = self.builder.build_call(strtoll, &arguments, "strtoll")
+            .try_as_basic_value()
+            .left()
+            .expect("strtoll returns the number it parsed.")
+            .into_int_value();
+        let fits = self.fits_in_cell(parsed, errno);
+        let parsed = self.builder.build_int_cast(parsed, cell_type, "parsed");
+        let parsed = self.builder.build_select(fits, parsed, cell_type.const_zero(), "parsed").into_int_value();
+        let end = self.builder.build_load(end_ptr.as_pointer_value(), "end").into_pointer_value();
+        let moved = self.builder.build_int_compare(IntPredicate::NE, end, string, "moved");
+        let last = self.builder.build_load(end, "last").into_int_value();
+        let at_end = self.builder.build_int_compare(IntPredicate::EQ, last, self.context.i8_type().const_zero(), "atEnd");
+        let whole = self.builder.build_and(moved, at_end, "whole");
+        let whole = self.builder.build_and(whole, fits, "whole");
+        self.builder.build_unconditional_branch(done);
+
+        self.builder.position_at_end(done);
+        let number = self.builder.build_phi(cell_type, "number");
+        number.add_incoming(&[(&cell_type.const_zero(), start), (&parsed, parse)]);
+        let found = self.builder.build_phi(self.context.bool_type(), "found");
+        found.add_incoming(&[(&self.context.bool_type().const_zero(), start), (&whole, parse)]);
+        let flag = self.builder.build_int_s_extend(found.as_basic_value().into_int_value(), cell_type, "flag");
+        (number.as_basic_value().into_int_value(), flag)
+    }
+
+    /// Reads a number from stdin with scanf, giving the number and a flag that is true
+    /// when there was one. Numbers are read as 64 bits, whatever the width of a cell, and
+    /// one that does not fit in a cell gives 0 and false like a missing one.
+    fn read_number(&self) -> (IntValue<'ctx>, IntValue<'ctx>) {
+        let i64_type = self.context.i64_type();
+        let string_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+        let scanf = self.libc_function("scanf", self.context.i32_type().fn_type(&[string_type.into()], true));
+        // A global rather than an alloca, which would grow the stack every time round a loop.
+        let target = self.module.get_global("meido_number_in").unwrap_or_else(|| {
+            let global = self.module.add_global(i64_type, None, "meido_number_in");
+            global.set_linkage(Linkage::Internal);
+            global.set_initializer(&i64_type.const_zero());
+            global
+        });
+        let format_string = self.builder.build_global_string_ptr("%lld", "number_format");
+        let errno = self.errno();
+        self.builder.build_store(errno, self.context.i32_type().const_zero());
+        let arguments: Vec<BasicMetadataValueEnum> = vec![format_string.as_pointer_value().into(), target.as_pointer_value().into()];
+        let matched = self.builder.build_call(scanf, &arguments, "scanf")
+            .try_as_basic_value()
+            .left()
+            .expect("scanf returns how many values it matched.")
+            .into_int_value();
+        let matched = self.builder.build_int_compare(IntPredicate::EQ, matched, self.context.i32_type().const_int(1, false), "matched");
+        let number = self.builder.build_load(target.as_pointer_value(), "number").into_int_value();
+        let found = self.builder.build_and(matched, self.fits_in_cell(number, errno), "found");
+        let number = self.builder.build_int_cast(number, self.cell_type(), "number");
+        let number = self.builder.build_select(found, number, self.cell_type().const_zero(), "number").into_int_value();
+        let flag = self.builder.build_int_s_extend(found, self.cell_type(), "flag");
+        (number, flag)
+    }
+
+    /// Where libc keeps errno, which scanf and strtoll set to ERANGE when a number does not
+    /// fit in 64 bits.
+    fn errno(&self) -> PointerValue<'ctx> {
+        let name = if cfg!(target_os = "macos") { "__error" } else { "__errno_location" };
+        let errno_type = self.context.i32_type().ptr_type(AddressSpace::Generic);
+        let function = self.libc_function(name, errno_type.fn_type(&[], false));
+        self.builder.build_call(function, &[], "errno")
+            .try_as_basic_value()
+            .left()
+            .expect("The errno function returns where errno is.")
+            .into_pointer_value()
+    }
+
+    /// Whether a 64-bit number that libc has just read fits in a cell, which it does not
+    /// when libc had to clamp it or when cells are narrower and it does not survive being
+    /// narrowed and widened again.
+    fn fits_in_cell(&self, number: IntValue<'ctx>, errno: PointerValue<'ctx>) -> IntValue<'ctx> {
+        let errno = self.builder.build_load(errno, "errno").into_int_value();
+        let erange = self.context.i32_type().const_int(ERANGE, false);
+        let in_range = self.builder.build_int_compare(IntPredicate::NE, errno, erange, "inRange");
+        if self.cell_bits >= 64 {
+            return in_range;
+        }
+        let narrowed = self.builder.build_int_truncate(number, self.cell_type(), "narrowed");
+        let widened = self.builder.build_int_s_extend(narrowed, number.get_type(), "widened");
+        let fits = self.builder.build_int_compare(IntPredicate::EQ, widened, number, "fits");
+        self.builder.build_and(in_range, fits, "fitsInCell")
+    }
+
+    fn intrinsic(&self, name: &str, fn_type: FunctionType<'ctx>) -> FunctionValue<'ctx> {
+        self.module.get_function(name).unwrap_or_else(|| self.module.add_function(name, fn_type, None))
+    }
+
+    /// Comparisons give an i1, which is sign-extended into a Forth flag: -1 when the
+    /// comparison holds and 0 when it does not.
+    fn compare(&self, predicate: IntPredicate, lhs: IntValue<'ctx>, rhs: IntValue<'ctx>) -> IntValue<'ctx> {
+        let holds = self.builder.build_int_compare(predicate, lhs, rhs, "compare");
+        self.builder.build_int_s_extend(holds, self.cell_type(), "flag")
+    }
+
+    fn compile_block(&mut self, exprs: &[Box<Expr>]) -> Result<(), CodegenError> {
+        for expr in exprs {
+            self.compile_expr(expr)?;
+        }
+        Ok(())
+    }
+
+    /// Starts a loop by branching to a new header block with a phi node for every value
+    /// on the stack, so the body sees the values of the previous iteration. The phis get
+    /// their first incoming value from the block the loop was entered from.
+    fn build_loop_header(&mut self, name: &str) -> (BasicBlock<'ctx>, Vec<PhiValue<'ctx>>) {
+        let preheader = self.builder.get_insert_block().unwrap();
+        let function = preheader.get_parent().unwrap();
+        let header = self.context.append_basic_block(function, name);
+        self.builder.build_unconditional_branch(header);
+        self.builder.position_at_end(header);
+
+        let mut phis = vec![];
+        for value in self.variables.iter_mut() {
+            let phi = self.builder.build_phi(self.cell_type(), "loopStack");
+            phi.add_incoming(&[(&*value, preheader)]);
+            *value = phi.as_basic_value().into_int_value();
+            phis.push(phi);
+        }
+        (header, phis)
+    }
+
+    /// Feeds the stack at the end of the loop body back into the header phis. The parser
+    /// has checked the body leaves the stack as deep as it found it, but it may hold more
+    /// or less of it in memory.
+    fn close_loop_header(&mut self, phis: &[PhiValue<'ctx>]) {
+        self.settle(phis.len());
+        let body_end = self.builder.get_insert_block().unwrap();
+        for (phi, value) in phis.iter().zip(&self.variables) {
+            phi.add_incoming(&[(value, body_end)]);
+        }
+    }
+
+    fn settle_branch(&mut self, end: BasicBlock<'ctx>, stack: Vec<IntValue<'ctx>>, depth: usize, merge: BasicBlock<'ctx>) -> Vec<IntValue<'ctx>> {
+        self.builder.position_at_end(end);
+        self.variables = stack;
+        self.settle(depth);
+        self.builder.build_unconditional_branch(merge);
+        std::mem::take(&mut self.variables)
+    }
+
+    /// Each word becomes a function that takes its inputs from the runtime data stack and
+    /// leaves its outputs there. Its body starts with nothing in SSA form, so anything it
+    /// pops before pushing values of its own is read from memory.
+    fn compile_definition(&mut self, name: &str, body: &[Box<Expr>]) -> Result<(), CodegenError> {
+        let resume = self.builder.get_insert_block().unwrap();
+        let fn_type = self.context.void_type().fn_type(&[], false);
+        let function = self.module.add_function(&word_symbol(name), fn_type, Some(Linkage::Internal));
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+
+        let outer_variables = std::mem::take(&mut self.variables);
+        let outer_indices = std::mem::take(&mut self.loop_indices);
+        self.compile_block(body)?;
+        self.flush_values();
+        self.builder.build_return(None);
+        self.variables = outer_variables;
+        self.loop_indices = outer_indices;
+        self.builder.position_at_end(resume);
+        Ok(())
+    }
+
+    /// The data stack in memory, below the values still held in SSA form. It is only
+    /// created once a program needs it.
+    fn runtime_stack(&self) -> (PointerValue<'ctx>, PointerValue<'ctx>) {
+        self.stack_globals("meido_stack", "meido_sp", self.cell_type().into())
+    }
+
+    /// Floats have a stack of their own, which is always kept in memory.
+    fn float_stack(&self) -> (PointerValue<'ctx>, PointerValue<'ctx>) {
+        self.stack_globals("meido_float_stack", "meido_fsp", self.context.f64_type().into())
+    }
+
+    fn stack_globals(&self, cells_name: &str, depth_name: &str, value_type: BasicTypeEnum<'ctx>) -> (PointerValue<'ctx>, PointerValue<'ctx>) {
+        let i32_type = self.context.i32_type();
+        let cells = self.module.get_global(cells_name).unwrap_or_else(|| {
+            let array_type = value_type.array_type(STACK_CELLS);
+            let global = self.module.add_global(array_type, None, cells_name);
+            global.set_linkage(Linkage::Internal);
+            global.set_initializer(&array_type.const_zero());
+            global
+        });
+        let depth = self.module.get_global(depth_name).unwrap_or_else(|| {
+            let global = self.module.add_global(i32_type, None, depth_name);
+            global.set_linkage(Linkage::Internal);
+            global.set_initializer(&i32_type.const_zero());
+            global
+        });
+        (cells.as_pointer_value(), depth.as_pointer_value())
+    }
+
+    /// The cell of memory at an address, stopping the program when the address is outside
+    /// of memory.
+    fn memory_cell(&self, address: IntValue<'ctx>, span: &Span) -> PointerValue<'ctx> {
+        // A negative address compares as a very large one, so one unsigned comparison
+        // covers both ends.
+        let size = self.cell_type().const_int(self.memory_cells as u64, false);
+        let inside = self.builder.build_int_compare(IntPredicate::ULT, address, size, "inside");
+        self.trap_unless(inside, "Address is outside of memory.", span);
+        self.memory_slot(address)
+    }
+
+    /// Checks that the `count` cells from `address` on are all in memory before ACCEPT,
+    /// ARG or ENV writes to them. A count of 0 or less writes nothing, so it always passes.
+    fn check_memory_range(&self, address: IntValue<'ctx>, count: IntValue<'ctx>, span: &Span) {
+        let zero = self.cell_type().const_zero();
+        let size = self.cell_type().const_int(self.memory_cells as u64, false);
+        let nothing = self.builder.build_int_compare(IntPredicate::SLE, count, zero, "nothing");
+        let starts_inside = self.builder.build_int_compare(IntPredicate::ULE, address, size, "startsInside");
+        // Only looked at when the address is inside, where the room left cannot overflow.
+        let room = self.builder.build_int_sub(size, address, "room");
+        let fits = self.builder.build_int_compare(IntPredicate::SLE, count, room, "fits");
+        let inside = self.builder.build_and(starts_inside, fits, "inside");
+        let allowed = self.builder.build_or(nothing, inside, "allowed");
+        self.trap_unless(allowed, "Buffer is outside of memory.", span);
+    }
+
+    /// Stops the program with a runtime error at `span` unless `condition` holds.
+    fn trap_unless(&self, condition: IntValue<'ctx>, message: &str, span: &Span) {
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let fault = self.context.append_basic_block(function, "memoryFault");
+        let fine = self.context.append_basic_block(function, "memoryFine");
+        self.builder.build_conditional_branch(condition, fine, fault);
+        self.builder.position_at_end(fault);
+        self.build_trap(&self.located(message, span));
+        self.builder.position_at_end(fine);
+    }
+
+    /// Variables live in one global array with a cell for each of them, so an address on
+    /// the stack is an index into it. The address has to have been checked already.
+    fn memory_slot(&self, address: IntValue<'ctx>) -> PointerValue<'ctx> {
+        let i32_type = self.context.i32_type();
+        let memory = self.module.get_global("meido_memory").unwrap_or_else(|| {
+            let array_type = self.cell_type().array_type(self.memory_cells);
+            let global = self.module.add_global(array_type, None, "meido_memory");
+            global.set_linkage(Linkage::Internal);
+            global.set_initializer(&array_type.const_zero());
+            global
+        });
+        unsafe { self.builder.build_in_bounds_gep(memory.as_pointer_value(), &[i32_type.const_zero(), address], "cell") }
+    }
+
+    fn push_to_memory(&self, value: IntValue<'ctx>) {
+        self.builder.build_call(self.push_helper(), &[value.into()], "push");
+    }
+
+    fn pop_from_memory(&self) -> IntValue<'ctx> {
+        self.builder.build_call(self.pop_helper(), &[], "pop")
+            .try_as_basic_value()
+            .left()
+            .expect("meido_pop returns the popped value.")
+            .into_int_value()
+    }
+
+    fn push_float(&self, value: FloatValue<'ctx>) {
+        let message = format!("Float stack overflow. The float stack holds at most {} values.", STACK_CELLS);
+        let function = self.stack_push_helper("meido_fpush", self.float_stack(), self.context.f64_type().into(), &message);
+        self.builder.build_call(function, &[value.into()], "fpush");
+    }
+
+    fn pop_float(&self) -> FloatValue<'ctx> {
+        let message = "Float stack underflow. The program tried to use a value the float stack does not have.";
+        let function = self.stack_pop_helper("meido_fpop", self.float_stack(), self.context.f64_type().into(), message);
+        self.builder.build_call(function, &[], "fpop")
+            .try_as_basic_value()
+            .left()
+            .expect("meido_fpop returns the popped value.")
+            .into_float_value()
+    }
+
+    fn push_helper(&self) -> FunctionValue<'ctx> {
+        let message = format!("Stack overflow. The stack holds at most {} values.", STACK_CELLS);
+        self.stack_push_helper("meido_push", self.runtime_stack(), self.cell_type().into(), &message)
+    }
+
+    fn pop_helper(&self) -> FunctionValue<'ctx> {
+        let message = "Stack underflow. The program tried to use a value the stack does not have.";
+        self.stack_pop_helper("meido_pop", self.runtime_stack(), self.cell_type().into(), message)
+    }
+
+    /// `meido_push` stores a value on top of the data stack in memory, and stops the
+    /// program when the stack is full. `meido_fpush` does the same for the float stack.
+    fn stack_push_helper(&self, name: &str, stack: (PointerValue<'ctx>, PointerValue<'ctx>), value_type: BasicTypeEnum<'ctx>, message: &str) -> FunctionValue<'ctx> {
+        if let Some(function) = self.module.get_function(name) {
+            return function;
+        }
+        let i32_type = self.context.i32_type();
+        let fn_type = self.context.void_type().fn_type(&[value_type.into()], false);
+        let function = self.module.add_function(name, fn_type, Some(Linkage::Internal));
+        let resume = self.builder.get_insert_block();
+        let entry = self.context.append_basic_block(function, "entry");
+        let overflow = self.context.append_basic_block(function, "overflow");
+        let push = self.context.append_basic_block(function, "push");
+
+        self.builder.position_at_end(entry);
+        let (cells, depth_ptr) = stack;
+        let depth = self.builder.build_load(depth_ptr, "depth").into_int_value();
+        let full = self.builder.build_int_compare(IntPredicate::SGE, depth, i32_type.const_int(STACK_CELLS as u64, false), "full");
+        self.builder.build_conditional_branch(full, overflow, push);
+
+        self.builder.position_at_end(overflow);
+        self.build_trap(message);
+
+        self.builder.position_at_end(push);
+        let value = function.get_nth_param(0).unwrap();
+        let slot = unsafe { self.builder.build_in_bounds_gep(cells, &[i32_type.const_zero(), depth], "slot") };
+        self.builder.build_store(slot, value);
+        let new_depth = self.builder.build_int_add(depth, i32_type.const_int(1, false), "newDepth");
+        self.builder.build_store(depth_ptr, new_depth);
+        self.builder.build_return(None);
+
+        if let Some(block) = resume {
+            self.builder.position_at_end(block);
+        }
+        function
+    }
+
+    /// `meido_pop` takes the value on top of the data stack in memory, and stops the
+    /// program when the stack is empty. This is where running out of values is caught
+    /// once the parser can no longer tell how deep the stack is, and always for floats.
+    fn stack_pop_helper(&self, name: &str, stack: (PointerValue<'ctx>, PointerValue<'ctx>), value_type: BasicTypeEnum<'ctx>, message: &str) -> FunctionValue<'ctx> {
+        if let Some(function) = self.module.get_function(name) {
+            return function;
+        }
+        let i32_type = self.context.i32_type();
+        let fn_type = value_type.fn_type(&[], false);
+        let function = self.module.add_function(name, fn_type, Some(Linkage::Internal));
+        let resume = self.builder.get_insert_block();
+        let entry = self.context.append_basic_block(function, "entry");
+        let underflow = self.context.append_basic_block(function, "underflow");
+        let pop = self.context.append_basic_block(function, "pop");
+
+        self.builder.position_at_end(entry);
+        let (cells, depth_ptr) = stack;
+        let depth = self.builder.build_load(depth_ptr, "depth").into_int_value();
+        let empty = self.builder.build_int_compare(IntPredicate::SLE, depth, i32_type.const_zero(), "empty");
+        self.builder.build_conditional_branch(empty, underflow, pop);
+
+        self.builder.position_at_end(underflow);
+        self.build_trap(message);
+
+        self.builder.position_at_end(pop);
+        let new_depth = self.builder.build_int_sub(depth, i32_type.const_int(1, false), "newDepth");
+        self.builder.build_store(depth_ptr, new_depth);
+        let slot = unsafe { self.builder.build_in_bounds_gep(cells, &[i32_type.const_zero(), new_depth], "slot") };
+        let value = self.builder.build_load(slot, "popped");
+        self.builder.build_return(Some(&value));
+
+        if let Some(block) = resume {
+            self.builder.position_at_end(block);
+        }
+        function
+    }
+
+    /// Dividing by zero, or the smallest cell by -1, is undefined in LLVM and crashes the
+    /// program on most machines. Both stop the program with an error pointing at the
+    /// operator instead.
+    fn check_division(&self, dividend: IntValue<'ctx>, divisor: IntValue<'ctx>, span: &Span) {
+        let function = self.builder.get_insert_block().unwrap().get_parent().unwrap();
+        let by_zero = self.context.append_basic_block(function, "divisionByZero");
+        let check_overflow = self.context.append_basic_block(function, "checkDivisionOverflow");
+        let overflow = self.context.append_basic_block(function, "divisionOverflow");
+        let divide = self.context.append_basic_block(function, "divide");
+
+        let is_zero = self.builder.build_int_compare(IntPredicate::EQ, divisor, self.cell_type().const_zero(), "isZero");
+        self.builder.build_conditional_branch(is_zero, by_zero, check_overflow);
+        self.builder.position_at_end(by_zero);
+        self.build_trap(&self.located("Division by zero.", span));
+
+        self.builder.position_at_end(check_overflow);
+        let smallest = self.cell_constant(i128::MIN >> (128 - self.cell_bits));
+        let minus_one = self.cell_constant(-1);
+        let is_smallest = self.builder.build_int_compare(IntPredicate::EQ, dividend, smallest, "isSmallest");
+        let is_minus_one = self.builder.build_int_compare(IntPredicate::EQ, divisor, minus_one, "isMinusOne");
+        let overflows = self.builder.build_and(is_smallest, is_minus_one, "overflows");
+        self.builder.build_conditional_branch(overflows, overflow, divide);
+        self.builder.position_at_end(overflow);
+        self.build_trap(&self.located("Division overflows a cell.", span));
+
+        self.builder.position_at_end(divide);
+    }
+
+    /// Adds the place in the source that `span` covers to a runtime error message, in the
+    /// same form as the compiler's own diagnostics.
+    fn located(&self, message: &str, span: &Span) -> String {
+        let (path, line, column) = self.sources.locate(span.start);
+        format!("{}\n --> {}:{}:{}", message, path, line, column)
+    }
+
+    /// Writes `message` to stderr and exits with `EXIT_RUNTIME_ERROR`. This ends the
+    /// current block.
+    fn build_trap(&self, message: &str) {
+        let text = format!("error: {}\n", message);
+        let i32_type = self.context.i32_type();
+        let size_type = self.context.ptr_sized_int_type(self.execution_engine.get_target_data(), None);
+        let write = self.module.get_function("write").unwrap_or_else(|| {
+            let string_type = self.context.i8_type().ptr_type(AddressSpace::Generic);
+            let fn_type = size_type.fn_type(&[i32_type.into(), string_type.into(), size_type.into()], false);
+            self.module.add_function("write", fn_type, Some(Linkage::External))
+        });
+        let exit = self.module.get_function("exit").unwrap_or_else(|| {
+            let fn_type = self.context.void_type().fn_type(&[i32_type.into()], false);
+            self.module.add_function("exit", fn_type, Some(Linkage::External))
+        });
+
+        let the_string = self.builder.build_global_string_ptr(&text, "trap_message");
+        let arguments: Vec<BasicMetadataValueEnum> = vec![
+            i32_type.const_int(2, false).into(),
+            the_string.as_pointer_value().into(),
+            size_type.const_int(text.len() as u64, false).into()
+        ];
+        self.builder.build_call(write, &arguments, "write");
+        self.builder.build_call(exit, &[i32_type.const_int(EXIT_RUNTIME_ERROR, false).into()], "exit");
+        self.builder.build_unreachable();
+    }
+
+    fn flush_values(&mut self) {
+        for value in std::mem::take(&mut self.variables) {
+            self.push_to_memory(value);
+        }
+    }
+
+    /// Moves values between memory and SSA form until exactly `depth` of them are held in
+    /// SSA form, without changing what is on the stack.
+    fn settle(&mut self, depth: usize) {
+        if self.variables.len() > depth {
+            let spilled = self.variables.len() - depth;
+            for value in self.variables.drain(..spilled).collect::<Vec<_>>() {
+                self.push_to_memory(value);
             }
         }
+        while self.variables.len() < depth {
+            let value = self.pop_from_memory();
+            self.variables.insert(0, value);
+        }
+    }
+
+    /// Prints the part of the stack held in memory, from the bottom up.
+    fn print_runtime_stack(&mut self) {
+        if self.module.get_global("meido_stack").is_none() {
+            return;
+        }
+        let (cells, depth_ptr) = self.runtime_stack();
+        let i32_type = self.context.i32_type();
+        let depth = self.builder.build_load(depth_ptr, "depth").into_int_value();
+        let entry = self.builder.get_insert_block().unwrap();
+        let function = entry.get_parent().unwrap();
+        let check_block = self.context.append_basic_block(function, "printCheck");
+        let print_block = self.context.append_basic_block(function, "printCell");
+        let done_block = self.context.append_basic_block(function, "printDone");
+        self.builder.build_unconditional_branch(check_block);
+
+        self.builder.position_at_end(check_block);
+        let index = self.builder.build_phi(i32_type, "printIndex");
+        let index_value = index.as_basic_value().into_int_value();
+        let more = self.builder.build_int_compare(IntPredicate::SLT, index_value, depth, "moreCells");
+        self.builder.build_conditional_branch(more, print_block, done_block);
+
+        self.builder.position_at_end(print_block);
+        let slot = unsafe { self.builder.build_in_bounds_gep(cells, &[i32_type.const_zero(), index_value], "slot") };
+        let cell = self.builder.build_load(slot, "cell").into_int_value();
+        self.print_cell(cell);
+        let next = self.builder.build_int_add(index_value, i32_type.const_int(1, false), "nextIndex");
+        self.builder.build_unconditional_branch(check_block);
+        index.add_incoming(&[(&i32_type.const_zero(), entry), (&next, print_block)]);
+
+        self.builder.position_at_end(done_block);
+    }
+
+    /// Prints a cell and a space after it. printf has no conversion for 128-bit integers,
+    /// so those are split into 18-digit chunks that each fit a long long, and only the
+    /// chunks from the first one that is not zero are printed.
+    fn print_cell(&self, value: IntValue<'ctx>) {
+        let printf = self.module.get_function("printf").unwrap();
+        if self.cell_bits < 128 {
+            let format = if self.cell_bits == 32 { "%d " } else { "%lld " };
+            let format_string = self.builder.build_global_string_ptr(format, "cell_format");
+            self.builder.build_call(printf, &[format_string.as_pointer_value().into(), value.into()], "printf");
+            return;
+        }
+        let i64_type = self.context.i64_type();
+        let chunk = self.cell_type().const_int(1_000_000_000_000_000_000, false);
+        let top = self.builder.build_int_signed_div(value, chunk.const_mul(chunk), "top");
+        let middle = self.builder.build_int_signed_div(value, chunk, "middle");
+        let middle = self.builder.build_int_signed_rem(middle, chunk, "middle");
+        let low = self.builder.build_int_signed_rem(value, chunk, "low");
+
+        // Every chunk has the sign of the whole value, so the sign is printed once in front
+        // and the chunks without it.
+        let magnitude = |part: IntValue<'ctx>| {
+            let part = self.builder.build_int_truncate(part, i64_type, "part");
+            let negative = self.builder.build_int_compare(IntPredicate::SLT, part, i64_type.const_zero(), "negative");
+            let negated = self.builder.build_int_neg(part, "negated");
+            self.builder.build_select(negative, negated, part, "magnitude").into_int_value()
+        };
+        let (top, middle, low) = (magnitude(top), magnitude(middle), magnitude(low));
+        let zero = self.cell_type().const_zero();
+        let negative = self.builder.build_int_compare(IntPredicate::SLT, value, zero, "negative");
+        let minus = self.builder.build_global_string_ptr("-", "minus").as_pointer_value();
+        let no_sign = self.builder.build_global_string_ptr("", "no_sign").as_pointer_value();
+        let sign = self.builder.build_select(negative, minus, no_sign, "sign");
+
+        let has_top = self.builder.build_int_compare(IntPredicate::NE, top, i64_type.const_zero(), "hasTop");
+        let has_middle = self.builder.build_int_compare(IntPredicate::NE, middle, i64_type.const_zero(), "hasMiddle");
+        let has_middle = self.builder.build_or(has_top, has_middle, "hasMiddle");
+        let three = self.builder.build_global_string_ptr("%s%lld%018lld%018lld ", "cell_format").as_pointer_value();
+        let two = self.builder.build_global_string_ptr("%s%lld%018lld ", "cell_format").as_pointer_value();
+        let one = self.builder.build_global_string_ptr("%s%lld ", "cell_format").as_pointer_value();
+        let format = self.builder.build_select(has_middle, two, one, "format").into_pointer_value();
+        let format = self.builder.build_select(has_top, three, format, "format");
+        let first = self.builder.build_select(has_middle, middle, low, "first").into_int_value();
+        let first = self.builder.build_select(has_top, top, first, "first");
+        let second = self.builder.build_select(has_top, middle, low, "second");
+        // printf ignores the arguments a shorter format has no use for.
+        let arguments: Vec<BasicMetadataValueEnum> = vec![format.into(), sign.into(), first.into(), second.into(), low.into()];
+        self.builder.build_call(printf, &arguments, "printf");
+    }
+
+    /// Pops from the values held in SSA form, or from memory once those run out. The
+    /// parser has checked the stack is deep enough either way.
+    fn pop_value(&mut self) -> IntValue<'ctx> {
+        match self.variables.pop() {
+            Some(value) => value,
+            None => self.pop_from_memory()
+        }
+    }
+
+    fn push_values(&mut self, values: &[IntValue<'ctx>]) {
+        self.variables.extend_from_slice(values);
     }
 
     pub fn define_printf(&self) {
@@ -113,25 +1349,55 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
     }
 
     pub fn build_main(&self) {
-        let main_fn_type = self.context.i32_type().fn_type(&[], false);
+        let i32_type = self.context.i32_type();
+        let argv_type = self.context.i8_type().ptr_type(AddressSpace::Generic).ptr_type(AddressSpace::Generic);
+        let main_fn_type = i32_type.fn_type(&[i32_type.into(), argv_type.into()], false);
         let main_fn = self.module.add_function("main", main_fn_type, Some(Linkage::External));
         let basic_block = self.context.append_basic_block(main_fn, "entry");
         self.builder.position_at_end(basic_block);
+
+        // Words are functions of their own, so the arguments are kept where they can reach them.
+        let argc = self.module.add_global(i32_type, None, "meido_argc");
+        argc.set_linkage(Linkage::Internal);
+        argc.set_initializer(&i32_type.const_zero());
+        let argv = self.module.add_global(argv_type, None, "meido_argv");
+        argv.set_linkage(Linkage::Internal);
+        argv.set_initializer(&argv_type.const_null());
+        self.builder.build_store(argc.as_pointer_value(), main_fn.get_nth_param(0).unwrap());
+        self.builder.build_store(argv.as_pointer_value(), main_fn.get_nth_param(1).unwrap());
     }
 
+    /// Returns the value on top of the stack from `main`, so it becomes the exit code of the
+    /// program, or 0 when the stack is empty.
     pub fn build_end_return(&self) {
         let i32_type = self.context.i32_type();
-        let i32_zero = i32_type.const_int(0, false);
-        self.builder.build_return(Some(&i32_zero));
+        let top = match (self.variables.last(), self.module.get_global("meido_sp")) {
+            (Some(value), _) => *value,
+            // The top of the stack is in memory, if there is anything there at all. The
+            // first cell is read in place of it when the stack is empty and then ignored.
+            (None, Some(depth_ptr)) => {
+                let (cells, _) = self.runtime_stack();
+                let depth = self.builder.build_load(depth_ptr.as_pointer_value(), "depth").into_int_value();
+                let empty = self.builder.build_int_compare(IntPredicate::SLE, depth, i32_type.const_zero(), "empty");
+                let below = self.builder.build_int_sub(depth, i32_type.const_int(1, false), "top");
+                let index = self.builder.build_select(empty, i32_type.const_zero(), below, "top").into_int_value();
+                let slot = unsafe { self.builder.build_in_bounds_gep(cells, &[i32_type.const_zero(), index], "slot") };
+                let value = self.builder.build_load(slot, "top").into_int_value();
+                self.builder.build_select(empty, self.cell_type().const_zero(), value, "top").into_int_value()
+            },
+            (None, None) => self.cell_type().const_zero()
+        };
+        let code = self.builder.build_int_cast(top, i32_type, "exitCode");
+        self.builder.build_return(Some(&code));
     }
 
-    pub fn write_to_file(&self) -> Result<(), String> {
+    pub fn write_to_file(&self) -> Result<(), CodegenError> {
         Target::initialize_all(&InitializationConfig::default());
         let target_triple = TargetMachine::get_default_triple();
         let cpu = TargetMachine::get_host_cpu_name().to_string();
         let features = TargetMachine::get_host_cpu_features().to_string();
 
-        let target = Target::from_triple(&target_triple).map_err(|e| format!("{:?}", e))?;
+        let target = Target::from_triple(&target_triple).map_err(|e| CodegenError::Llvm(e.to_string()))?;
         let target_machine = target
             .create_target_machine(
                 &target_triple,
@@ -141,11 +1407,11 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
                 RelocMode::DynamicNoPic,
                 CodeModel::Default,
             )
-            .ok_or_else(|| "Unable to create target machine!".to_string())?;
+            .ok_or_else(|| CodegenError::Llvm("Unable to create target machine!".to_string()))?;
 
         let buff = target_machine
             .write_to_memory_buffer(&self.module, FileType::Assembly)
-            .expect("couldn't compile to assembly");
+            .map_err(|e| CodegenError::Llvm(e.to_string()))?;
 
         println!(
             "Assembly:\n{}",
@@ -154,6 +1420,244 @@ impl<'a, 'ctx> Compiler<'a, 'ctx> {
 
         target_machine
             .write_to_file(&self.module, FileType::Object, "a.o".as_ref())
-            .map_err(|e| format!("{:?}", e))
+            .map_err(|e| CodegenError::Llvm(e.to_string()))
+    }
+}
+
+fn word_symbol(name: &str) -> String {
+    format!("word.{}", name)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::os::raw::c_char;
+    use logos::Logos;
+    use crate::parser::Parser;
+    use crate::tokens::Token;
+
+    /// Compiles `code` the way main does, checks that LLVM accepts the module, and hands
+    /// it to `check` along with the execution engine to run it with.
+    fn compile<T>(code: &str, overflow: OverflowMode, cell_bits: u32,
+                  check: impl FnOnce(&Module, &ExecutionEngine) -> T) -> T {
+        let mut parser: Parser = Parser::new(Token::lexer(code)).with_cell_bits(cell_bits);
+        assert_eq!(parser.parse_program(), vec![]);
+        let context = Context::create();
+        let module = context.create_module("MeidoLang");
+        let builder = context.create_builder();
+        let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None).unwrap();
+        let mut codegen = Compiler {
+            context: &context,
+            builder: &builder,
+            module: &module,
+            variables: vec![],
+            loop_indices: vec![],
+            memory_cells: parser.variable_count() as u32,
+            overflow,
+            cell_bits,
+            sources: &parser.sources,
+            execution_engine: &execution_engine,
+            printf_defined: false,
+            string_count: 1
+        };
+        codegen.build_main();
+        for expr in &parser.stack {
+            codegen.compile_expr(expr).unwrap();
+        }
+        codegen.build_end_return();
+        if let Err(err) = module.verify() {
+            panic!("{}\n{}", err.to_string(), module.print_to_string().to_string());
+        }
+        check(&module, &execution_engine)
+    }
+
+    /// Runs `code` with `args` after the program's own name and gives its exit code, which
+    /// is the value left on top of the stack.
+    fn run_with(code: &str, overflow: OverflowMode, cell_bits: u32, args: &[&str]) -> i32 {
+        compile(code, overflow, cell_bits, |_, execution_engine| {
+            let arguments: Vec<CString> = std::iter::once("test.meido").chain(args.iter().copied())
+                .map(|argument| CString::new(argument).unwrap())
+                .collect();
+            let mut argv: Vec<*const c_char> = arguments.iter().map(|argument| argument.as_ptr()).collect();
+            argv.push(std::ptr::null());
+            unsafe {
+                let main = execution_engine
+                    .get_function::<unsafe extern "C" fn(i32, *const *const c_char) -> i32>("main")
+                    .unwrap();
+                main.call(arguments.len() as i32, argv.as_ptr())
+            }
+        })
+    }
+
+    fn run(code: &str) -> i32 {
+        run_with(code, OverflowMode::Wrapping, 32, &[])
+    }
+
+    /// Writes `n` the way a program does, one digit at a time.
+    fn number(n: u128) -> String {
+        let digits: Vec<String> = n.to_string().chars()
+            .map(|digit| "レム".repeat(digit.to_digit(10).unwrap() as usize))
+            .collect();
+        digits.join("エミリア") + "ラム"
+    }
+
+    /// The LLVM IR of `code`, for the runtime errors that would end the test if it ran.
+    fn ir(code: &str, overflow: OverflowMode) -> String {
+        compile(code, overflow, 32, |module, _| module.print_to_string().to_string())
+    }
+
+    #[test]
+    fn compile_expr_runs_an_if_that_leaves_the_stack_at_different_depths() {
+        let code = "レムラム IF レムレムラム レムレムレムラム ELSE レムレムレムレムラム THEN +";
+        assert_eq!(run(&format!("レムラム レムラム {}", code)), 5);
+        assert_eq!(run(&format!("レムラム エミリアラム {}", code)), 5);
+    }
+
+    #[test]
+    fn compile_expr_runs_a_loop_that_grows_the_stack() {
+        assert_eq!(run("エミリアラム BEGIN DUP レムラム + DUP レムレムレムラム = UNTIL + + +"), 6);
+    }
+
+    #[test]
+    fn compile_expr_runs_a_do_loop() {
+        assert_eq!(run("エミリアラム レムエミリアラム エミリアラム DO I + LOOP"), 45);
+    }
+
+    #[test]
+    fn compile_expr_runs_a_recursive_word() {
+        let code = ": 【階乗】 DUP レムラム > IF DUP レムラム - 【階乗】 * THEN ; レムレムレムレムレムラム 【階乗】";
+        assert_eq!(run(code), 120);
+    }
+
+    #[test]
+    fn compile_expr_runs_a_word_that_leaves_a_varying_number_of_values() {
+        let code = ": 【分ける】 DUP IF DUP THEN ; レムレムラム エミリアラム 【分ける】 DROP レムラム 【分ける】 + +";
+        assert_eq!(run(code), 4);
+    }
+
+    #[test]
+    fn compile_expr_divides_by_a_divisor_known_only_at_runtime() {
+        let code = "レムエミリアレムレムラム レムラム IF レムレムレムラム ELSE レムレムラム THEN /";
+        assert_eq!(run(code), 4);
+        assert_eq!(run("レムレムレムレムレムレムレムラム NEGATE レムレムラム MOD"), -1);
+    }
+
+    #[test]
+    fn compile_expr_traps_division_by_zero_with_its_place_in_the_source() {
+        let code = "レムラム レムラム IF エミリアラム ELSE レムラム THEN /";
+        let ir = ir(code, OverflowMode::Wrapping);
+        assert!(ir.contains("error: Division by zero.\\0A --> :1:36"));
+        assert!(ir.contains("error: Division overflows a cell."));
+    }
+
+    #[test]
+    fn compile_expr_wraps_around_by_default() {
+        assert_eq!(run(&format!("{} レムラム + 0<", number(i32::MAX as u128))), -1);
+    }
+
+    #[test]
+    fn compile_expr_saturates_in_saturating_mode() {
+        let max = number(i32::MAX as u128);
+        let code = format!("{0} レムラム + {0} = {0} NEGATE レムレムラム - {0} NEGATE レムラム - = AND", max);
+        assert_eq!(run_with(&code, OverflowMode::Saturating, 32, &[]), -1);
+    }
+
+    #[test]
+    fn compile_expr_traps_overflow_in_checked_mode() {
+        assert_eq!(run_with("レムレムラム レムレムレムラム *", OverflowMode::Checked, 32, &[]), 6);
+        assert!(ir("レムレムラム レムレムレムラム *", OverflowMode::Checked).contains("error: Arithmetic overflow."));
+    }
+
+    #[test]
+    fn compile_expr_follows_the_cell_width() {
+        let code = format!("{} {} /", number(3_000_000_000), number(1000));
+        assert_eq!(run_with(&code, OverflowMode::Checked, 64, &[]), 3_000_000);
+        let ten = number(10_000_000_000);
+        let code = format!("{0} {0} * {1} = {1} {0} / {0} = AND", ten, number(100_000_000_000_000_000_000));
+        assert_eq!(run_with(&code, OverflowMode::Checked, 128, &[]), -1);
+    }
+
+    #[test]
+    fn compile_expr_pops_what_it_prints() {
+        assert_eq!(run("レムラム レムレムラム ."), 1);
+        assert_eq!(run(&format!("レムレムラム {} EMIT CR", number(65))), 2);
+        assert_eq!(run("レムラム レムレムラム .S"), 2);
+        assert_eq!(run("レムレムレムラム レムレムレムレムラム レムラム IF レムレムラム THEN . .S"), 4);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn build_end_return_exits_with_the_top_of_the_stack() {
+        assert_eq!(run(""), 0);
+        assert_eq!(run("レムレムラム レムレムレムラム * さよなら"), 6);
+        assert_eq!(run("レムレムラム エミリアラム IF DROP THEN"), 2);
+        assert_eq!(run("レムラム エミリアラム IF DROP THEN DROP"), 0);
+    }
+
+    #[test]
+    fn compile_expr_exits_with_the_code_bye_pops() {
+        assert!(ir("レムレムラム BYE", OverflowMode::Wrapping).contains("call void @exit(i32 2)"));
+    }
+
+    #[test]
+    fn compile_expr_reads_the_arguments() {
+        assert_eq!(run_with("ARGC", OverflowMode::Wrapping, 32, &["12", "x"]), 3);
+        assert_eq!(run_with("レムラム ARG>NUMBER DROP", OverflowMode::Wrapping, 32, &["12"]), 12);
+        assert_eq!(run_with("レムラム ARG>NUMBER", OverflowMode::Wrapping, 32, &["12x"]), 0);
+        assert_eq!(run_with("レムラム ARG>NUMBER +", OverflowMode::Wrapping, 32, &["3000000000"]), 0);
+        let code = format!("レムラム ARG>NUMBER DROP {} =", number(3_000_000_000));
+        assert_eq!(run_with(&code, OverflowMode::Wrapping, 64, &["3000000000"]), -1);
+        let code = "レムエミリアラム BUFFER: 【値】 レムラム 【値】 レムエミリアラム ARG 【値】 @ +";
+        assert_eq!(run_with(code, OverflowMode::Wrapping, 32, &["12"]), 2 + '1' as i32);
+        let code = "レムレムラム 【値】 レムエミリアラム ARG";
+        assert_eq!(run_with(&format!("レムエミリアラム BUFFER: 【値】 {}", code), OverflowMode::Wrapping, 32, &[]), -1);
+    }
+
+    #[test]
+    fn compile_expr_reads_the_environment() {
+        std::env::set_var("MEIDOLANG_TEST", "ab");
+        let code = "レムエミリアラム BUFFER: 【値】 【値】 レムエミリアラム ENV スバルMEIDOLANG_TEST君";
+        assert_eq!(run(code), 2);
+        let code = "レムエミリアラム BUFFER: 【値】 【値】 レムエミリアラム ENV スバルMEIDOLANG_MISSING君";
+        assert_eq!(run(code), -1);
+    }
+
+    #[test]
+    fn compile_expr_checks_addresses_are_in_memory() {
+        assert_eq!(run("VARIABLE 【甲】 レムレムレムラム 【甲】 ! 【甲】 @"), 3);
+        assert!(ir("レムラム エミリアラム !", OverflowMode::Wrapping).contains("error: Address is outside of memory."));
+        let code = "レムエミリアラム BUFFER: 【行】 【行】 レムエミリアレムラム ACCEPT";
+        assert!(ir(code, OverflowMode::Wrapping).contains("error: Buffer is outside of memory."));
+    }
+
+    #[test]
+    fn compile_expr_takes_the_branch_the_flag_picks() {
+        let code = "IF レムレムラム ELSE レムレムレムラム THEN レムエミリアラム +";
+        assert_eq!(run(&format!("レムラム {}", code)), 12);
+        assert_eq!(run(&format!("エミリアラム {}", code)), 13);
+        assert_eq!(run("レムレムレムレムラム エミリアラム IF DUP + THEN"), 4);
+        assert_eq!(run("レムラム レムラム IF エミリアラム IF レムラム ELSE レムレムラム THEN + THEN"), 3);
+    }
+
+    #[test]
+    fn compile_expr_runs_begin_loops() {
+        assert_eq!(run("エミリアラム レムエミリアラム BEGIN SWAP レムラム + SWAP レムラム - DUP 0= UNTIL DROP"), 10);
+        let code = "エミリアラム レムレムレムレムレムラム BEGIN DUP WHILE SWAP OVER + SWAP レムラム - REPEAT DROP";
+        assert_eq!(run(code), 15);
+    }
+
+    #[test]
+    fn compile_expr_runs_nested_do_loops() {
+        let code = "エミリアラム レムレムレムラム エミリアラム DO レムレムラム エミリアラム DO I + LOOP LOOP";
+        assert_eq!(run(code), 3);
+        assert_eq!(run("エミリアラム レムレムレムラム エミリアラム DO I + LOOP"), 3);
+    }
+
+    #[test]
+    fn compile_expr_calls_words() {
+        let code = ": 【二乗】 DUP * ; : 【四乗】 【二乗】 【二乗】 ; レムレムラム 【四乗】 レムレムレムラム 【二乗】 +";
+        assert_eq!(run(code), 25);
+        let code = ": 【フィボ】 DUP レムラム > IF DUP レムラム - 【フィボ】 SWAP レムレムラム - 【フィボ】 + THEN ; \
+                    レムエミリアラム 【フィボ】";
+        assert_eq!(run(code), 55);
+    }
+}
@@ -0,0 +1,166 @@
+use logos::Span;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span
+}
+
+impl Diagnostic {
+    pub fn new(message: &str, span: Span) -> Self {
+        Diagnostic {
+            message: message.to_string(),
+            span
+        }
+    }
+
+    /// Renders the diagnostic the way rustc does: the message, the file location, the
+    /// offending source line and a caret under the span. Carets are measured in display
+    /// columns, so a full-width character such as `レ` gets two of them.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let end = self.span.end.min(source.len()).max(start);
+        let (line, column) = location(source, start);
+
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let source_line = &source[line_start..line_end];
+
+        let padding = UnicodeWidthStr::width(&source[line_start..start]);
+        let underline = UnicodeWidthStr::width(&source[start..end.min(line_end)]).max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            self.message,
+            gutter, file_name, line, column,
+            gutter,
+            line, source_line,
+            gutter, " ".repeat(padding), "^".repeat(underline)
+        )
+    }
+}
+
+/// Every file that makes up a program, laid out one after another so that spans from
+/// different files never overlap. A span from anywhere in the program says which file it
+/// points into, and where in that file.
+#[derive(Debug, Clone)]
+pub struct SourceMap<'a> {
+    files: Vec<SourceFile<'a>>
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile<'a> {
+    pub path: String,
+    pub source: &'a str,
+    // Where the file's first byte sits among the spans of the whole program.
+    pub start: usize
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(path: &str, source: &'a str) -> Self {
+        SourceMap {
+            files: vec![SourceFile { path: path.to_string(), source, start: 0 }]
+        }
+    }
+
+    /// Adds a file after the ones already there and returns where its spans start. There
+    /// is a byte between files, so a span at the very end of one is not the start of the
+    /// next.
+    pub fn add(&mut self, path: &str, source: &'a str) -> usize {
+        let last = self.files.last().expect("A source map starts with the main file.");
+        let start = last.start + last.source.len() + 1;
+        self.files.push(SourceFile { path: path.to_string(), source, start });
+        start
+    }
+
+    pub fn main_file(&self) -> &SourceFile<'a> {
+        &self.files[0]
+    }
+
+    /// The file that `offset` points into.
+    pub fn file(&self, offset: usize) -> &SourceFile<'a> {
+        self.files.iter().rev()
+            .find(|file| file.start <= offset)
+            .expect("The main file starts at 0.")
+    }
+
+    /// The path, line and column that `offset` points at.
+    pub fn locate(&self, offset: usize) -> (&str, usize, usize) {
+        let file = self.file(offset);
+        let (line, column) = location(file.source, offset - file.start);
+        (&file.path, line, column)
+    }
+
+    /// Renders a diagnostic against the file its span points into.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        let file = self.file(diagnostic.span.start);
+        let span = diagnostic.span.start - file.start..diagnostic.span.end.saturating_sub(file.start);
+        Diagnostic::new(&diagnostic.message, span).render(&file.path, file.source)
+    }
+}
+
+/// Converts a byte offset into a one-based line and column. Columns count characters,
+/// not bytes, so they line up with what an editor shows.
+pub fn location(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_counts_lines_and_characters() {
+        let source = "レムラム\nベティ+";
+        assert_eq!(location(source, 0), (1, 1));
+        assert_eq!(location(source, 6), (1, 3));
+        assert_eq!(location(source, 13), (2, 1));
+        assert_eq!(location(source, 22), (2, 4));
+    }
+
+    #[test]
+    fn render_points_a_caret_at_the_span() {
+        let source = "レムラム\nベティ+";
+        let diagnostic = Diagnostic::new("Not enough variables to perform an operation", 22..23);
+        assert_eq!(diagnostic.render("test.meido", source),
+            "error: Not enough variables to perform an operation\n \
+            --> test.meido:2:4\n  \
+            |\n\
+            2 | ベティ+\n  \
+            |       ^");
+    }
+
+    #[test]
+    fn source_map_renders_against_the_file_a_span_points_into() {
+        let mut sources = SourceMap::new("main.meido", "レムラム\n");
+        let start = sources.add("lib.meido", "ベティ+");
+        assert_eq!(start, 14);
+        assert_eq!(sources.locate(3), ("main.meido", 1, 2));
+        assert_eq!(sources.locate(start + 9), ("lib.meido", 1, 4));
+        let diagnostic = Diagnostic::new("Not enough variables to perform an operation", start + 9..start + 10);
+        assert_eq!(sources.render(&diagnostic),
+            "error: Not enough variables to perform an operation\n \
+            --> lib.meido:1:4\n  \
+            |\n\
+            1 | ベティ+\n  \
+            |       ^");
+    }
+
+    #[test]
+    fn render_underlines_full_width_tokens_with_two_carets_per_character() {
+        let source = "レムラムラ";
+        let diagnostic = Diagnostic::new("Number was broken.", 12..15);
+        assert_eq!(diagnostic.render("test.meido", source),
+            "error: Number was broken.\n \
+            --> test.meido:1:5\n  \
+            |\n\
+            1 | レムラムラ\n  \
+            |         ^^");
+    }
+}
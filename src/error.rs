@@ -0,0 +1,171 @@
+use std::error::Error;
+use std::fmt;
+use logos::Span;
+use crate::diagnostics::Diagnostic;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum LexError {
+    UnknownCharacter { ch: char, span: Span, suggestion: Option<&'static str> },
+    UnterminatedComment { span: Span },
+    UnknownWord { word: String, span: Span }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseError {
+    Lex(LexError),
+    StackUnderflow { op: &'static str, span: Span },
+    MissingFinalizer { span: Span },
+    NumberOverflow { bits: u32, span: Span },
+    FloatOverflow { span: Span },
+    DivisionByZero { span: Span },
+    UnknownEscape { span: Span },
+    UnterminatedString { span: Span },
+    UnterminatedIf { span: Span },
+    UnterminatedLoop { span: Span },
+    IndexOutsideLoop { span: Span },
+    MissingWordName { span: Span },
+    MissingVariableName { span: Span },
+    MissingBufferSize { span: Span },
+    MissingEnvironmentName { span: Span },
+    MissingIncludePath { span: Span },
+    IncludeFailed { path: String, reason: String, span: Span },
+    IncludeCycle { path: String, span: Span },
+    UnterminatedDefinition { span: Span },
+    NestedDefinition { span: Span },
+    DuplicateWord { name: String, span: Span },
+    UndefinedWord { name: String, span: Span },
+    WordUnderflow { name: String, inputs: usize, span: Span },
+    UnexpectedToken { span: Span },
+    UnexpectedEnd { span: Span }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CodegenError {
+    Llvm(String)
+}
+
+impl LexError {
+    pub fn span(&self) -> Span {
+        match self {
+            LexError::UnknownCharacter { span, .. } |
+            LexError::UnterminatedComment { span } |
+            LexError::UnknownWord { span, .. } => span.clone()
+        }
+    }
+}
+
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::Lex(err) => err.span(),
+            ParseError::StackUnderflow { span, .. } |
+            ParseError::MissingFinalizer { span } |
+            ParseError::NumberOverflow { span, .. } |
+            ParseError::FloatOverflow { span } |
+            ParseError::DivisionByZero { span } |
+            ParseError::UnknownEscape { span } |
+            ParseError::UnterminatedString { span } |
+            ParseError::UnterminatedIf { span } |
+            ParseError::UnterminatedLoop { span } |
+            ParseError::IndexOutsideLoop { span } |
+            ParseError::MissingWordName { span } |
+            ParseError::MissingVariableName { span } |
+            ParseError::MissingBufferSize { span } |
+            ParseError::MissingEnvironmentName { span } |
+            ParseError::MissingIncludePath { span } |
+            ParseError::IncludeFailed { span, .. } |
+            ParseError::IncludeCycle { span, .. } |
+            ParseError::UnterminatedDefinition { span } |
+            ParseError::NestedDefinition { span } |
+            ParseError::DuplicateWord { span, .. } |
+            ParseError::UndefinedWord { span, .. } |
+            ParseError::WordUnderflow { span, .. } |
+            ParseError::UnexpectedToken { span } |
+            ParseError::UnexpectedEnd { span } => span.clone()
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnknownCharacter { ch, suggestion: Some(keyword), .. } =>
+                write!(f, "Unknown character '{}'. Did you mean '{}'?", ch, keyword),
+            LexError::UnknownCharacter { ch, suggestion: None, .. } => write!(f, "Unknown character '{}'.", ch),
+            LexError::UnterminatedComment { .. } => write!(f, "Comment was never closed with ）."),
+            LexError::UnknownWord { word, .. } => write!(f, "Unknown word '{}'.", word)
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Lex(err) => err.fmt(f),
+            ParseError::StackUnderflow { op, .. } =>
+                write!(f, "Not enough variables on the stack to perform '{}'.", op),
+            ParseError::MissingFinalizer { .. } => write!(f, "Number was not finished with ラム."),
+            ParseError::NumberOverflow { bits, .. } => write!(f, "Number does not fit in a {}-bit cell.", bits),
+            ParseError::FloatOverflow { .. } => write!(f, "Float has more digits than can be read."),
+            ParseError::DivisionByZero { .. } => write!(f, "Division by zero."),
+            ParseError::UnknownEscape { .. } => write!(f, "Unknown escape. Strings may only escape \\n, \\t, \\君 and \\\\."),
+            ParseError::UnterminatedString { .. } => write!(f, "String was never ended with 君."),
+            ParseError::UnterminatedIf { .. } => write!(f, "IF was never ended with THEN."),
+            ParseError::UnterminatedLoop { .. } => write!(f, "Loop was never ended."),
+            ParseError::IndexOutsideLoop { .. } => write!(f, "I can only be used inside DO … LOOP."),
+            ParseError::MissingWordName { .. } => write!(f, "Expected the name of the word to define, such as 【名前】."),
+            ParseError::UnterminatedDefinition { .. } => write!(f, "Word definition was never ended with ;."),
+            ParseError::MissingVariableName { .. } => write!(f, "Expected the name of the variable to define, such as 【名前】."),
+            ParseError::MissingBufferSize { .. } =>
+                write!(f, "BUFFER: needs the number of cells to set aside written as a number right before it."),
+            ParseError::MissingEnvironmentName { .. } =>
+                write!(f, "Expected the name of the environment variable as a string, such as スバルHOME君."),
+            ParseError::MissingIncludePath { .. } =>
+                write!(f, "Expected the path of the file to include as a string, such as スバル共通.meido君."),
+            ParseError::IncludeFailed { path, reason, .. } => write!(f, "Could not include {}: {}", path, reason),
+            ParseError::IncludeCycle { path, .. } =>
+                write!(f, "{} is already being included, so including it again would never end.", path),
+            ParseError::NestedDefinition { .. } =>
+                write!(f, "Words and variables can only be defined outside of other words, branches and loops."),
+            ParseError::DuplicateWord { name, .. } => write!(f, "【{}】 is already defined.", name),
+            ParseError::UndefinedWord { name, .. } => write!(f, "No word or variable named 【{}】 is defined.", name),
+            ParseError::WordUnderflow { name, inputs, .. } =>
+                write!(f, "Not enough variables on the stack to call 【{}】, which takes {}.", name, inputs),
+            ParseError::UnexpectedToken { .. } => write!(f, "Unexpected token."),
+            ParseError::UnexpectedEnd { .. } => write!(f, "Unexpected end of program.")
+        }
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::Llvm(message) => write!(f, "LLVM failure: {}", message)
+        }
+    }
+}
+
+impl Error for LexError {}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Lex(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl Error for CodegenError {}
+
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> Self {
+        ParseError::Lex(err)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(err: &ParseError) -> Self {
+        Diagnostic::new(&err.to_string(), err.span())
+    }
+}
@@ -1,4 +1,6 @@
 mod codegen;
+mod diagnostics;
+mod error;
 mod parser;
 mod tokens;
 
@@ -7,15 +9,32 @@ use logos::Logos;
 extern crate inkwell;
 
 use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::ffi::CString;
 use std::io::{Read};
+use std::os::raw::c_char;
 use clap::{App, Arg};
 use inkwell::context::Context;
 use inkwell::OptimizationLevel;
 
-use crate::codegen::Compiler;
+use crate::codegen::{Compiler, OverflowMode};
+use crate::diagnostics::Diagnostic;
+use crate::error::CodegenError;
 use crate::tokens::Token;
 use crate::parser::Parser;
 
+// Distinct exit codes let tools wrapping the compiler tell a bad program apart from
+// a failure inside LLVM without matching on the printed message.
+const EXIT_IO_ERROR: i32 = 1;
+const EXIT_BAD_PROGRAM: i32 = 2;
+const EXIT_CODEGEN_ERROR: i32 = 3;
+
+// LLVM failures are not caused by any particular part of the program, so there is no
+// place in it to point at.
+fn exit_with_codegen_error(err: &CodegenError) -> ! {
+    eprintln!("error: {}", err);
+    std::process::exit(EXIT_CODEGEN_ERROR);
+}
 
 fn main() {
 
@@ -32,55 +51,116 @@ fn main() {
             .required(true)
             .value_name("FILE")
             .help("Input file for reading code."))
+        .arg(Arg::with_name("overflow")
+            .long("overflow")
+            .takes_value(true)
+            .possible_values(&["checked", "wrapping", "saturating"])
+            .value_name("MODE")
+            .help("What + - and * do when the result does not fit in a cell. Overrides an overflow pragma in the program. Defaults to wrapping."))
+        .arg(Arg::with_name("cell-width")
+            .long("cell-width")
+            .takes_value(true)
+            .possible_values(&["32", "64", "128"])
+            .default_value("32")
+            .value_name("BITS")
+            .help("How many bits a cell on the stack has."))
+        .arg(Arg::with_name("args")
+            .multiple(true)
+            .last(true)
+            .value_name("ARGS")
+            .help("Arguments passed on to the program when it is run with --jit, written after --."))
         .get_matches();
     let jit_enabled = matches.is_present("jit");
     let path = matches.value_of("input").expect("No input file specified. See --help");
-    let mut file = std::fs::File::open(path).unwrap();
+    let cell_bits: u32 = matches.value_of("cell-width").unwrap().parse().expect("clap only accepts the possible values.");
     let mut code = String::new();
-    file.read_to_string(&mut code).unwrap();
+    if let Err(err) = std::fs::File::open(path).and_then(|mut file| file.read_to_string(&mut code)) {
+        eprintln!("error: Could not read {}: {}", path, err);
+        std::process::exit(EXIT_IO_ERROR);
+    }
 
     let lex = Token::lexer(&code);
 
-    let mut parser: Parser = Parser::new( lex);
-    while let Ok(_) = parser.parse_expr() {/*Call the function until it can no longer be called.*/}
+    let mut parser: Parser = Parser::new( lex).with_cell_bits(cell_bits).with_path(path);
+    let errors = parser.parse_program();
+    if !errors.is_empty() {
+        for err in &errors {
+            eprintln!("{}\n", parser.sources.render(&Diagnostic::from(err)));
+        }
+        eprintln!("error: aborting due to {} previous error{}", errors.len(), if errors.len() == 1 { "" } else { "s" });
+        std::process::exit(EXIT_BAD_PROGRAM);
+    }
+
+    // `※ overflow: checked` in the program picks its mode unless the command line does.
+    let overflow = match matches.value_of("overflow") {
+        Some(mode) => mode.parse().expect("clap only accepts the possible values."),
+        None => match parser.pragma("overflow") {
+            Some((mode, span)) => mode.parse().unwrap_or_else(|_| {
+                let message = "Unknown overflow mode. Expected checked, wrapping or saturating.";
+                eprintln!("{}", parser.sources.render(&Diagnostic::new(message, span)));
+                std::process::exit(EXIT_BAD_PROGRAM);
+            }),
+            None => OverflowMode::Wrapping
+        }
+    };
+
+    // Memory is one array, which LLVM sizes with 32 bits.
+    let memory_cells = u32::try_from(parser.variable_count()).unwrap_or_else(|_| {
+        eprintln!("error: Variables and buffers need {} cells of memory, but there can be at most {}.",
+                  parser.variable_count(), u32::MAX);
+        std::process::exit(EXIT_BAD_PROGRAM);
+    });
 
     let context = Context::create();
     let module = context.create_module("MeidoLang");
     let builder = context.create_builder();
-    let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None).unwrap();
+    let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None)
+        .unwrap_or_else(|err| exit_with_codegen_error(&CodegenError::Llvm(err.to_string())));
     let mut codegen = Compiler {
         context: &context,
         builder: builder.borrow(),
         module: module.borrow(),
         variables: vec![],
+        loop_indices: vec![],
+        memory_cells,
+        overflow,
+        cell_bits,
+        sources: &parser.sources,
         execution_engine: execution_engine.borrow(),
         printf_defined: false,
-        string_count: 1,
-        print_stack_count: 1
+        string_count: 1
     };
 
     codegen.build_main();
     parser.stack.reverse();
     while let Some(an_expr) = parser.stack.pop() {
-        codegen.compile_expr(&an_expr).expect("Unable to compile a statement.");
+        if let Err(err) = codegen.compile_expr(&an_expr) {
+            exit_with_codegen_error(&err);
+        }
     }
     codegen.build_end_return();
 
     if jit_enabled {
-        let maybe_fn = unsafe { execution_engine.get_function::<unsafe extern "C" fn() -> i32>("main") };
+        let maybe_fn = unsafe { execution_engine.get_function::<unsafe extern "C" fn(i32, *const *const c_char) -> i32>("main") };
         let compiled_fn = match maybe_fn {
             Ok(f) => f,
-            Err(err) => {
-                println!("!> Error during execution: {:?}", err);
-                return ()
-            }
+            Err(err) => exit_with_codegen_error(&CodegenError::Llvm(format!("{:?}", err)))
         };
-        unsafe {
-            println!("=> {}", compiled_fn.call());
-        }
+        // The program sees the path of its source as argument 0, as a binary sees its own.
+        let arguments: Vec<CString> = std::iter::once(path)
+            .chain(matches.values_of("args").into_iter().flatten())
+            .map(|argument| CString::new(argument).expect("Command line arguments cannot hold a NUL."))
+            .collect();
+        let mut argv: Vec<*const c_char> = arguments.iter().map(|argument| argument.as_ptr()).collect();
+        argv.push(std::ptr::null());
+        // The program's exit code is the compiler's too, as it would be for the binary.
+        let code = unsafe { compiled_fn.call(arguments.len() as i32, argv.as_ptr()) };
+        std::process::exit(code);
     }
     else {
-        codegen.write_to_file().expect("Could not write to file.");
+        if let Err(err) = codegen.write_to_file() {
+            exit_with_codegen_error(&err);
+        }
     }
 
 }
@@ -89,6 +169,7 @@ fn main() {
 #[allow(unused_must_use)]
 mod tests {
     use super::*;
+    use crate::tokens::StringPart;
     #[test]
     fn lexer_lexes_a_program() {
         let mut lex = Token::lexer("レムレムラムベティレムレムラムベティ+ベティスバルtest君さよなら.");
@@ -137,13 +218,17 @@ mod tests {
             assert_eq!(lex.span(), 64..73);
             assert_eq!(lex.slice(), "スバル");
 
-        assert_eq!(lex.next(), Some(Token::STRINGLITERAL));
-            assert_eq!(lex.span(), 73..77);
-            assert_eq!(lex.slice(), "test");
+        let mut string_lex = lex.morph::<StringPart>();
+
+        assert_eq!(string_lex.next(), Some(StringPart::STRINGLITERAL));
+            assert_eq!(string_lex.span(), 73..77);
+            assert_eq!(string_lex.slice(), "test");
+
+        assert_eq!(string_lex.next(), Some(StringPart::STRINGEND));
+            assert_eq!(string_lex.span(), 77..80);
+            assert_eq!(string_lex.slice(), "君");
 
-        assert_eq!(lex.next(), Some(Token::STRINGEND));
-            assert_eq!(lex.span(), 77..80);
-            assert_eq!(lex.slice(), "君");
+        let mut lex = string_lex.morph::<Token>();
 
         assert_eq!(lex.next(), Some(Token::PROGRAMEND));
             assert_eq!(lex.span(), 80..92);
@@ -1,13 +1,25 @@
 
-use logos::Logos;
-use crate::tokens::Token;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use logos::{Logos, Span};
+use crate::diagnostics::SourceMap;
+use crate::error::{LexError, ParseError};
+use crate::tokens::{suggest_keyword, StringPart, Token};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     Binary {
-        op: char,
+        op: BinaryOp,
         left: Box<Expr>,
-        right: Box<Expr>
+        right: Box<Expr>,
+        span: Span
+    },
+
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        span: Span
     },
 
     Call {
@@ -17,138 +29,1127 @@ pub enum Expr {
 
     Number(Box<Val>),
 
+    // Floats are kept on a stack of their own, so the parser does not track them with
+    // the cells in `variables`.
+    Float(f64),
+
+    FloatWord(FloatWord),
+
+    // S>F and F>S, which move a value between the two stacks.
+    CellToFloat,
+    FloatToCell,
+
     PrintStack,
 
+    // `.` and EMIT pop the value they print, and CR prints a newline.
+    PrintTop,
+    Emit,
+    NewLine,
+
+    // KEY pushes a byte read from stdin, or -1 at the end of it. ACCEPT takes an address
+    // and a count, reads a line into memory there and pushes how many characters it kept.
+    // READ-NUMBER pushes a number and a flag that is false when there was none to read.
+    Key,
+    Accept {
+        span: Span
+    },
+    ReadNumber,
+
+    // ARGC pushes how many arguments the program was given, counting its own name as
+    // argument 0. ARG takes an argument's number, an address and a count and copies the
+    // argument into memory there, and ENV does the same for an environment variable. Both
+    // push how many characters they kept, or -1 when there is nothing to copy. ARG>NUMBER
+    // replaces an argument's number with the number the argument spells and a flag.
+    ArgCount,
+    Argument {
+        span: Span
+    },
+    ArgumentNumber,
+    Environment {
+        name: String,
+        span: Span
+    },
+
+    // The program ends with the value on top of the stack as its exit code, or 0 when
+    // the stack is empty. BYE pops the exit code and ends the program wherever it is.
     ProgramEnd,
+    Bye,
+
+    // Forth's stack words, which rearrange the values already on the stack.
+    Dup,
+    Drop,
+    Swap,
+    Over,
+    Rot,
+    Nip,
+    Tuck,
+
+    // /MOD leaves both the remainder and the quotient, so unlike the other arithmetic it
+    // works on the stack directly.
+    DivMod {
+        span: Span
+    },
+
+    // Branches and loops are `dynamic` when the depth of the stack after them depends on
+    // the data, so the compiler has to keep the whole stack in memory across them.
+    If {
+        then_branch: Vec<Box<Expr>>,
+        else_branch: Vec<Box<Expr>>,
+        span: Span,
+        dynamic: bool
+    },
+
+    BeginUntil {
+        body: Vec<Box<Expr>>,
+        span: Span,
+        dynamic: bool
+    },
+
+    BeginWhile {
+        condition: Vec<Box<Expr>>,
+        body: Vec<Box<Expr>>,
+        span: Span,
+        dynamic: bool
+    },
+
+    DoLoop {
+        body: Vec<Box<Expr>>,
+        span: Span,
+        dynamic: bool
+    },
+
+    LoopIndex,
+
+    // `INCLUDE スバル名前.meido君`. The included file's expressions go into the program in
+    // its place, so this only records that it was there.
+    Include {
+        path: String
+    },
+
+    // `: 【名前】 … ;`, which is compiled to a function of its own.
+    Definition {
+        name: String,
+        body: Vec<Box<Expr>>,
+        span: Span
+    },
+
+    CallWord {
+        name: String,
+        span: Span
+    },
+
+    // `VARIABLE 【名前】` sets aside a cell of memory, and the variable's name pushes the
+    // address of that cell.
+    DeclareVariable {
+        name: String,
+        cell: usize
+    },
+
+    VariableAddress {
+        name: String,
+        cell: usize
+    },
+
+    // The span of ! and @ is kept so an address outside of memory can be reported.
+    Store {
+        span: Span
+    },
+
+    Fetch {
+        span: Span
+    },
+
+    // An operand that was already on the stack when the block it is used in began.
+    Stacked,
 
     StringPrint(Box<String>)
 }
 
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    And,
+    Or,
+    Xor,
+    Mod,
+    Min,
+    Max,
+    LShift,
+    RShift
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum UnaryOp {
+    ZeroEqual,
+    ZeroLess,
+    ZeroGreater,
+    Invert,
+    Negate,
+    Abs
+}
+
+/// The words that work on the float stack alone.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FloatWord {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Negate,
+    Print,
+    Dup,
+    Drop,
+    Swap,
+    Over
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Val {
-    pub n: i32
+    // Wide enough for the widest cell. The parser checks each number fits the cell width
+    // the program is compiled for.
+    pub n: i128
+}
+
+/// How many values a word takes from the stack and how many it leaves in their place.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct StackEffect {
+    inputs: usize,
+    outputs: usize
 }
 
+// A word body is checked as if its caller had left this many values on the stack, which
+// is then the most a word can take.
+const WORD_INPUT_LIMIT: usize = 64;
+
+// How many times a recursive word is parsed before giving up on its stack effect.
+const MAX_EFFECT_ATTEMPTS: usize = 8;
+
 pub struct Parser<'a> {
     lex: logos::Lexer<'a, Token>,
     current: Option<Token>,
     pub stack: Vec<Box<Expr>>,
-    variables: Vec<Box<Expr>>
+    variables: Vec<Box<Expr>>,
+    errors: Vec<ParseError>,
+    counted_loops: usize,
+    blocks: usize,
+    // A word without a stack effect takes and leaves a different number of values
+    // depending on the data.
+    words: HashMap<String, Option<StackEffect>>,
+    cells: HashMap<String, usize>,
+    // How many cells of memory the variables and buffers declared so far take.
+    memory_size: usize,
+    defining: Option<String>,
+    recursed: bool,
+    // Set once the depth of the stack depends on the data. From then on the parser cannot
+    // tell how deep the stack is, and running out of values is caught at runtime instead.
+    dynamic_depth: bool,
+    // The shallowest depth any operation has reached into the stack, which tells how many
+    // values the word being defined takes.
+    low_water: usize,
+    cell_bits: u32,
+    // The files the program is made of. Spans count from the start of the main file, with
+    // each included file after it, and `offset` is where the file being read starts.
+    pub sources: SourceMap<'a>,
+    offset: usize,
+    // The files being included, innermost last, and every file included so far.
+    including: Vec<PathBuf>,
+    included: Vec<PathBuf>
 }
 
 impl<'a> Parser<'a> {
     pub fn new(mut l: logos::Lexer<'a, Token>) -> Self {
         let cur = l.next();
+        let sources = SourceMap::new("", l.source());
         Parser {
             lex: l,
             current: cur,
             stack: Vec::new(),
-            variables: Vec::new()
-        }
-    }
-
-    pub fn parse_expr(&mut self) -> Result<Expr, &'static str> {
-        if self.current == Some(Token::PLUS) ||
-            self.current == Some(Token::MINUS) ||
-            self.current == Some(Token::MULT) ||
-            self.current == Some(Token::DIV) {
-            let val = self.parse_binary_expr();
-            let return_val = val.clone();
-            let value = Box::from(Box::from(val.clone().expect("Value was not present.")));
-            self.stack.push(*value);
-            self.variables.push(Box::new(val.unwrap()).clone());
-            return return_val;
-        } else if self.current == Some(Token::ONE) {
-            let val = self.parse_nb_expr().unwrap();
-            let value = Box::new(Expr::Number(Box::new(val)));
-            self.variables.push(value.clone());
-            self.stack.push(value);
-            return Ok(Expr::Number(Box::new(val)));
+            variables: Vec::new(),
+            errors: Vec::new(),
+            counted_loops: 0,
+            blocks: 0,
+            words: HashMap::new(),
+            cells: HashMap::new(),
+            memory_size: 0,
+            defining: None,
+            recursed: false,
+            dynamic_depth: false,
+            low_water: 0,
+            cell_bits: 32,
+            sources,
+            offset: 0,
+            including: Vec::new(),
+            included: Vec::new()
+        }
+    }
+
+    /// Sets the path of the main file, which included files are found relative to.
+    pub fn with_path(mut self, path: &str) -> Self {
+        let source = self.sources.main_file().source;
+        self.sources = SourceMap::new(path, source);
+        self.including = vec![canonical(Path::new(path))];
+        self
+    }
+
+    /// Sets how many bits a cell has, 32 unless told otherwise, so numbers too big for
+    /// one are reported.
+    pub fn with_cell_bits(mut self, bits: u32) -> Self {
+        self.cell_bits = bits;
+        self
+    }
+
+    /// The span of the current token, or an empty span at the end of the source once
+    /// the lexer has run out of tokens.
+    pub fn span(&self) -> Span {
+        if self.current.is_none() {
+            let end = self.offset + self.lex.source().len();
+            end..end
+        } else {
+            self.shifted(self.lex.span())
+        }
+    }
+
+    /// Moves a span the lexer gives, which counts from the start of the file being read,
+    /// to where that file sits among the spans of the whole program.
+    fn shifted(&self, span: Span) -> Span {
+        self.offset + span.start..self.offset + span.end
+    }
+
+    /// The spans of every comment the lexer has skipped so far.
+    pub fn comments(&self) -> &[Span] {
+        &self.lex.extras
+    }
+
+    /// Looks for a `※ name: value` line comment, which sets an option for the whole
+    /// program, and returns the value along with its span.
+    pub fn pragma(&self, name: &str) -> Option<(&'a str, Span)> {
+        let source = self.lex.source();
+        self.comments().iter().find_map(|span| {
+            let comment = &source[span.clone()];
+            let rest = comment.strip_prefix('※')?.trim_start().strip_prefix(name)?;
+            let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+            let value = rest.trim_end();
+            let start = span.end - rest.len();
+            Some((value, start..start + value.len()))
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.current.is_none()
+    }
+
+    /// How many cells of memory the program's variables need.
+    pub fn variable_count(&self) -> usize {
+        self.memory_size
+    }
+
+    /// Parses the whole program, collecting every error instead of stopping at the first
+    /// one. After an error the parser resumes at the next token it has not looked at yet,
+    /// skipping the offending token when it could not get past it.
+    pub fn parse_program(&mut self) -> Vec<ParseError> {
+        while !self.is_finished() {
+            self.parse_expr_recovering();
+        }
+        std::mem::take(&mut self.errors)
+    }
+
+    fn parse_expr_recovering(&mut self) {
+        let start = self.span();
+        if let Err(err) = self.parse_expr() {
+            self.errors.push(err);
+            if self.span() == start {
+                self.current = self.lex.next();
+            }
+        }
+    }
+
+    /// Parses expressions into a list of their own until one of `terminators` or the end
+    /// of the program. Errors inside the block are recorded and skipped like those at the
+    /// top level, so one mistake in a branch does not hide the rest of the program.
+    fn parse_block(&mut self, terminators: &[Token]) -> Vec<Box<Expr>> {
+        let outer = std::mem::take(&mut self.stack);
+        self.blocks += 1;
+        while !self.is_finished() && !terminators.iter().any(|t| self.current.as_ref() == Some(t)) {
+            self.parse_expr_recovering();
+        }
+        self.blocks -= 1;
+        std::mem::replace(&mut self.stack, outer)
+    }
+
+    /// Checks the stack holds the values an operation works on, and notes how far down
+    /// it reaches so the stack effect of a word can be worked out from its body.
+    fn check_depth(&mut self, needed: usize, op: &'static str, span: &Span) -> Result<(), ParseError> {
+        if self.variables.len() < needed && !self.dynamic_depth {
+            return Err(ParseError::StackUnderflow { op, span: span.clone() });
+        }
+        self.reach(needed);
+        Ok(())
+    }
+
+    fn reach(&mut self, needed: usize) {
+        if self.variables.len() < needed {
+            // Only possible once the depth is dynamic, where the values come from below
+            // what the parser can see.
+            let missing = needed - self.variables.len();
+            self.variables.splice(0..0, vec![Box::new(Expr::Stacked); missing]);
+        }
+        self.low_water = self.low_water.min(self.variables.len() - needed);
+    }
+
+    pub fn parse_expr(&mut self) -> Result<Expr, ParseError> {
+        if self.current.as_ref().and_then(binary_operator).is_some() {
+            let val = self.parse_binary_expr()?;
+            self.stack.push(Box::new(val.clone()));
+            self.variables.push(Box::new(val.clone()));
+            return Ok(val);
+        } else if self.current.as_ref().and_then(unary_operator).is_some() {
+            let val = self.parse_unary_expr()?;
+            self.stack.push(Box::new(val.clone()));
+            self.variables.push(Box::new(val.clone()));
+            return Ok(val);
+        } else if matches!(self.current, Some(Token::ONE) | Some(Token::TEN) | Some(Token::HUNDRED) |
+                Some(Token::THOUSAND) | Some(Token::POINT)) {
+            let value = Box::new(self.parse_nb_expr()?);
+            if let Expr::Number(_) = *value {
+                self.variables.push(value.clone());
+            }
+            self.stack.push(value.clone());
+            return Ok(*value);
+        } else if self.current.as_ref().and_then(float_word).is_some() ||
+                matches!(self.current, Some(Token::CELLTOFLOAT) | Some(Token::FLOATTOCELL)) {
+            let val = self.parse_float_word()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if matches!(self.current, Some(Token::DUP) | Some(Token::DROP) | Some(Token::SWAP) |
+                Some(Token::OVER) | Some(Token::ROT) | Some(Token::NIP) | Some(Token::TUCK)) {
+            self.parse_stack_word()
+        } else if self.current == Some(Token::DIVMOD) {
+            let val = self.parse_div_mod()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::IF) {
+            let val = self.parse_if_expr()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::BEGIN) {
+            let val = self.parse_begin_expr()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::DO) {
+            let val = self.parse_do_expr()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::I) {
+            if self.counted_loops == 0 {
+                return Err(ParseError::IndexOutsideLoop { span: self.span() });
+            }
+            self.current = self.lex.next();
+            self.variables.push(Box::new(Expr::LoopIndex));
+            self.stack.push(Box::new(Expr::LoopIndex));
+            Ok(Expr::LoopIndex)
+        } else if self.current == Some(Token::COLON) {
+            let val = self.parse_definition()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if matches!(self.current, Some(Token::VARIABLE) | Some(Token::BUFFER)) {
+            let val = self.parse_variable()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if matches!(self.current, Some(Token::STORE) | Some(Token::FETCH)) {
+            let val = self.parse_memory_word()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::WORDNAME) {
+            let val = self.parse_word_call()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
         } else if self.current == Some(Token::STRINGSTART) {
             self.parse_string_expr()
         } else if self.current == Some(Token::PRINTSTACK) {
             self.parse_print_stack_expr()
+        } else if matches!(self.current, Some(Token::KEY) | Some(Token::ACCEPT) | Some(Token::READNUMBER) |
+                Some(Token::ARGC) | Some(Token::ARG) | Some(Token::ARGNUMBER) | Some(Token::ENV)) {
+            let val = self.parse_input_word()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if matches!(self.current, Some(Token::DOT) | Some(Token::EMIT) | Some(Token::CR)) {
+            let val = self.parse_output_word()?;
+            self.stack.push(Box::new(val.clone()));
+            Ok(val)
+        } else if self.current == Some(Token::INCLUDE) {
+            self.parse_include()
+        } else if self.current == Some(Token::BYE) {
+            let span = self.span();
+            self.check_depth(1, "BYE", &span)?;
+            self.variables.pop();
+            self.current = self.lex.next();
+            self.stack.push(Box::new(Expr::Bye));
+            Ok(Expr::Bye)
         } else if self.current == Some(Token::PROGRAMEND) {
             //Ignore everything else. Program should terminate.
-            self.lex = Logos::lexer("");
-            self.current = self.lex.next();
+            self.current = None;
             self.stack.push(Box::new(Expr::ProgramEnd));
             Ok(Expr::ProgramEnd)
+        } else if self.current == Some(Token::Error) {
+            Err(self.parse_unknown_character().into())
+        } else if self.current == Some(Token::UNKNOWNWORD) {
+            let err = LexError::UnknownWord { word: self.lex.slice().to_string(), span: self.span() };
+            self.current = self.lex.next();
+            Err(err.into())
+        } else if self.current == Some(Token::UNTERMINATEDCOMMENT) {
+            let span = self.span();
+            self.current = self.lex.next();
+            Err(LexError::UnterminatedComment { span }.into())
+        } else if self.current.is_none() {
+            Err(ParseError::UnexpectedEnd { span: self.span() })
         } else {
-            Err("Unknown At this time.")
+            Err(ParseError::UnexpectedToken { span: self.span() })
+        }
+    }
+
+    /// The lexer produces one error token per unknown character. When the characters
+    /// start a keyword, the whole attempt is reported at once with the keyword suggested.
+    fn parse_unknown_character(&mut self) -> LexError {
+        let start = self.lex.span().start;
+        let rest = &self.lex.source()[start..];
+        let ch = rest.chars().next().expect("Error tokens always cover a character.");
+        let suggestion = suggest_keyword(rest);
+        if let Some((_, len)) = suggestion {
+            let lexed = self.lex.span().len();
+            if len > lexed {
+                self.lex.bump(len - lexed);
+            }
+        }
+        let span = self.shifted(start..self.lex.span().end);
+        self.current = self.lex.next();
+        LexError::UnknownCharacter {
+            ch,
+            span,
+            suggestion: suggestion.map(|(keyword, _)| keyword)
         }
     }
 
-    fn parse_binary_expr(&mut self) -> Result<Expr, &'static str> {
-        if self.variables.len() < 2 {
-            return Err("Not enough variables to perform an operation")
+    fn parse_binary_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let (op, name) = match self.current.as_ref().and_then(binary_operator) {
+            Some(operator) => operator,
+            None => return Err(ParseError::UnexpectedToken { span })
+        };
+        self.check_depth(2, name, &span)?;
+        if matches!(op, BinaryOp::Div | BinaryOp::Mod) {
+            self.check_divisor(&span)?;
         }
-        let right = self.stack.pop().expect("Strange Value Exists in the stack.");
-        let left = self.stack.pop().expect("Strange Value Exists in the stack.");
+        // Inside a block the operands may have been pushed before the block began.
+        let right = self.stack.pop().unwrap_or_else(|| Box::new(Expr::Stacked));
+        let left = self.stack.pop().unwrap_or_else(|| Box::new(Expr::Stacked));
         self.variables.pop();
         self.variables.pop();
-        let op: char;
-        if self.current == Some(Token::PLUS) {
-            op = '+';
-        } else if self.current == Some(Token::MINUS) {
-            op = '-';
-        } else if self.current == Some(Token::MULT) {
-            op = '*';
-        } else if self.current == Some(Token::DIV) {
-            op = '/';
-        } else {
-            return Err("Unknown Operator");
-        }
         self.current = self.lex.next();
 
         Ok(Expr::Binary {
             op,
             left,
-            right
+            right,
+            span
+        })
+    }
+
+    fn parse_div_mod(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        self.check_depth(2, "/MOD", &span)?;
+        self.check_divisor(&span)?;
+        self.current = self.lex.next();
+        let val = Expr::DivMod { span };
+        self.variables.truncate(self.variables.len() - 2);
+        self.variables.push(Box::new(val.clone()));
+        self.variables.push(Box::new(val.clone()));
+        Ok(val)
+    }
+
+    /// A divisor written as a literal zero is reported before the program runs. Any other
+    /// zero divisor is caught by a check in the compiled program.
+    fn check_divisor(&self, span: &Span) -> Result<(), ParseError> {
+        match self.variables.last().map(|divisor| &**divisor) {
+            Some(Expr::Number(divisor)) if divisor.n == 0 => Err(ParseError::DivisionByZero { span: span.clone() }),
+            _ => Ok(())
+        }
+    }
+
+    fn parse_unary_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let (op, name) = match self.current.as_ref().and_then(unary_operator) {
+            Some(operator) => operator,
+            None => return Err(ParseError::UnexpectedToken { span })
+        };
+        self.check_depth(1, name, &span)?;
+        let operand = self.stack.pop().unwrap_or_else(|| Box::new(Expr::Stacked));
+        self.variables.pop();
+        self.current = self.lex.next();
+
+        Ok(Expr::Unary {
+            op,
+            operand,
+            span
         })
     }
 
-    fn parse_nb_expr(&mut self) -> Result<Val, &'static str> {
-        if self.current == Some(Token::ONE) {
+    /// Stack words are kept in the program as they are written, while `variables` is
+    /// rearranged the same way so later words are checked against the right depth.
+    fn parse_stack_word(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let (expr, name, needed) = match self.current {
+            Some(Token::DUP) => (Expr::Dup, "DUP", 1),
+            Some(Token::DROP) => (Expr::Drop, "DROP", 1),
+            Some(Token::SWAP) => (Expr::Swap, "SWAP", 2),
+            Some(Token::OVER) => (Expr::Over, "OVER", 2),
+            Some(Token::ROT) => (Expr::Rot, "ROT", 3),
+            Some(Token::NIP) => (Expr::Nip, "NIP", 2),
+            Some(Token::TUCK) => (Expr::Tuck, "TUCK", 2),
+            _ => return Err(ParseError::UnexpectedToken { span })
+        };
+        self.check_depth(needed, name, &span)?;
+        self.current = self.lex.next();
+
+        let top = self.variables.len() - 1;
+        match expr {
+            Expr::Dup => self.variables.push(self.variables[top].clone()),
+            Expr::Drop => { self.variables.pop(); },
+            Expr::Swap => self.variables.swap(top, top - 1),
+            Expr::Over => self.variables.push(self.variables[top - 1].clone()),
+            Expr::Rot => {
+                let third = self.variables.remove(top - 2);
+                self.variables.push(third);
+            },
+            Expr::Nip => { self.variables.remove(top - 1); },
+            Expr::Tuck => self.variables.insert(top - 1, self.variables[top].clone()),
+            _ => unreachable!()
+        }
+        self.stack.push(Box::new(expr.clone()));
+        Ok(expr)
+    }
+
+    /// IF pops a flag and runs its first branch when the flag is not zero, and the ELSE
+    /// branch, if there is one, otherwise. When the branches leave the stack at different
+    /// depths, the depth after THEN depends on which of them ran. A value the branches
+    /// leave differently is only known at runtime, so it becomes `Stacked` after THEN.
+    fn parse_if_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        self.check_depth(1, "IF", &span)?;
+        self.current = self.lex.next();
+        self.variables.pop();
+
+        let outer_dynamic = self.dynamic_depth;
+        let before = self.variables.clone();
+        let then_branch = self.parse_block(&[Token::ELSE, Token::THEN]);
+        let then_dynamic = std::mem::replace(&mut self.dynamic_depth, outer_dynamic);
+        let mut else_branch = Vec::new();
+        let mut else_variables = before;
+        if self.current == Some(Token::ELSE) {
             self.current = self.lex.next();
-            return Ok(Val {
-                n: 1 + self.parse_nb_expr().unwrap().n
-            });
-        } else if self.current == Some(Token::FINALIZER) {
+            let then_variables = std::mem::replace(&mut self.variables, else_variables);
+            else_branch = self.parse_block(&[Token::THEN]);
+            else_variables = std::mem::replace(&mut self.variables, then_variables);
+        }
+
+        if self.current != Some(Token::THEN) {
+            return Err(ParseError::UnterminatedIf { span });
+        }
+        self.current = self.lex.next();
+        let dynamic = then_dynamic || self.dynamic_depth || self.variables.len() != else_variables.len();
+        self.dynamic_depth = dynamic;
+        self.forget_changed(&else_variables);
+        Ok(Expr::If {
+            then_branch,
+            else_branch,
+            span,
+            dynamic
+        })
+    }
+
+    /// BEGIN … UNTIL runs its body until the flag it leaves is not zero. BEGIN … WHILE …
+    /// REPEAT checks its condition first and leaves the loop as soon as it is zero. A body
+    /// that changes the depth of the stack makes the depth after the loop depend on how
+    /// many times it ran. A value the body changes is only known at runtime after the loop,
+    /// since the body may have run any number of times.
+    fn parse_begin_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        self.current = self.lex.next();
+        let entry = self.variables.clone();
+        let depth = entry.len();
+        let body = self.parse_block(&[Token::UNTIL, Token::WHILE]);
+
+        if self.current == Some(Token::UNTIL) {
+            let dynamic = self.check_loop_depth(depth + 1);
             self.current = self.lex.next();
-            return Ok(Val { n: 0 });
+            self.variables.pop();
+            self.forget_changed(&entry);
+            return Ok(Expr::BeginUntil { body, span, dynamic });
+        }
+        if self.current != Some(Token::WHILE) {
+            return Err(ParseError::UnterminatedLoop { span });
+        }
+        if self.variables.len() <= depth && !self.dynamic_depth {
+            return Err(ParseError::StackUnderflow { op: "WHILE", span: self.span() });
+        }
+        self.check_depth(1, "WHILE", &span)?;
+        self.current = self.lex.next();
+        self.variables.pop();
+        let after_condition = self.variables.clone();
+        let condition = body;
+        let body = self.parse_block(&[Token::REPEAT]);
+        if self.current != Some(Token::REPEAT) {
+            return Err(ParseError::UnterminatedLoop { span });
+        }
+        let dynamic = self.check_loop_depth(depth);
+        self.current = self.lex.next();
+        // When the body changes anything, the condition sees other values on later passes,
+        // so nothing it left on the first pass is known to be what the loop leaves.
+        let body_changed = self.variables != entry;
+        self.variables = after_condition;
+        if body_changed {
+            self.variables.iter_mut().for_each(|value| *value = Box::new(Expr::Stacked));
         } else {
-            return Err("Number was broken.")
+            self.forget_changed(&entry);
+        }
+        Ok(Expr::BeginWhile { condition, body, span, dynamic })
+    }
+
+    /// DO pops a limit and a starting index and runs its body once for every index up to
+    /// the limit. The body always runs at least once, as in Forth, and can push the
+    /// current index with I.
+    fn parse_do_expr(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        self.check_depth(2, "DO", &span)?;
+        self.current = self.lex.next();
+        self.variables.pop();
+        self.variables.pop();
+        let depth = self.variables.len();
+
+        let entry = self.variables.clone();
+
+        self.counted_loops += 1;
+        let body = self.parse_block(&[Token::LOOP]);
+        self.counted_loops -= 1;
+        if self.current != Some(Token::LOOP) {
+            return Err(ParseError::UnterminatedLoop { span });
+        }
+        let dynamic = self.check_loop_depth(depth);
+        self.current = self.lex.next();
+        self.forget_changed(&entry);
+        Ok(Expr::DoLoop { body, span, dynamic })
+    }
+
+    /// `: 【名前】 … ;` defines a word. Its stack effect is how deep its body reaches into
+    /// the values its caller left and how many it leaves in their place. A word that calls
+    /// itself is parsed again with the effect found the last time, until the effect stops
+    /// changing. One that never settles is treated as having no fixed effect at all.
+    fn parse_definition(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        if self.blocks > 0 {
+            self.errors.push(ParseError::NestedDefinition { span: span.clone() });
+        }
+        self.current = self.lex.next();
+        if self.current != Some(Token::WORDNAME) {
+            return Err(ParseError::MissingWordName { span: self.span() });
+        }
+        let name = word_name(self.lex.slice());
+        if self.is_defined(&name) {
+            self.errors.push(ParseError::DuplicateWord { name: name.clone(), span: self.span() });
+        }
+        self.current = self.lex.next();
+
+        let outer_variables = std::mem::take(&mut self.variables);
+        let outer_low_water = self.low_water;
+        let outer_loops = std::mem::replace(&mut self.counted_loops, 0);
+        let outer_recursed = self.recursed;
+        let outer_dynamic = self.dynamic_depth;
+        let outer_defining = self.defining.replace(name.clone());
+        let (start_lex, start_current, start_errors) = (self.lex.clone(), self.current.clone(), self.errors.len());
+
+        let mut effect = Some(StackEffect { inputs: 0, outputs: 0 });
+        let mut attempts = 1;
+        let body = loop {
+            self.words.insert(name.clone(), effect);
+            self.variables = vec![Box::new(Expr::Stacked); WORD_INPUT_LIMIT];
+            self.low_water = WORD_INPUT_LIMIT;
+            self.recursed = false;
+            self.dynamic_depth = false;
+            let body = self.parse_block(&[Token::SEMICOLON]);
+            let found = if self.dynamic_depth {
+                None
+            } else {
+                Some(StackEffect {
+                    inputs: WORD_INPUT_LIMIT - self.low_water,
+                    outputs: self.variables.len().saturating_sub(self.low_water)
+                })
+            };
+            if !self.recursed || found == effect || self.current != Some(Token::SEMICOLON) {
+                effect = found;
+                break body;
+            }
+            effect = if attempts == MAX_EFFECT_ATTEMPTS { None } else { found };
+            attempts += 1;
+            self.lex = start_lex.clone();
+            self.current = start_current.clone();
+            self.errors.truncate(start_errors);
+        };
+
+        self.words.insert(name.clone(), effect);
+        self.variables = outer_variables;
+        self.low_water = outer_low_water;
+        self.counted_loops = outer_loops;
+        self.recursed = outer_recursed;
+        self.dynamic_depth = outer_dynamic;
+        self.defining = outer_defining;
+        if self.current != Some(Token::SEMICOLON) {
+            return Err(ParseError::UnterminatedDefinition { span });
         }
+        self.current = self.lex.next();
+        Ok(Expr::Definition { name, body, span })
     }
 
-    fn parse_string_expr(&mut self) -> Result<Expr, &'static str> {
+    /// `VARIABLE 【名前】` sets aside one cell and `n BUFFER: 【名前】` sets aside n of them,
+    /// where n has to be written as a number right before BUFFER: so its size is known
+    /// before the program runs. Either way the name pushes the address of the first cell.
+    fn parse_variable(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        if self.blocks > 0 {
+            self.errors.push(ParseError::NestedDefinition { span: span.clone() });
+        }
+        let size = if self.current == Some(Token::BUFFER) {
+            let size = match self.stack.last().map(|size| &**size) {
+                Some(Expr::Number(size)) => u32::try_from(size.n).ok().filter(|size| *size > 0),
+                _ => None
+            };
+            match size {
+                Some(size) => {
+                    self.stack.pop();
+                    self.variables.pop();
+                    size as usize
+                },
+                // The name is still declared, so uses of it are not reported as well.
+                None => {
+                    self.errors.push(ParseError::MissingBufferSize { span });
+                    1
+                }
+            }
+        } else {
+            1
+        };
         self.current = self.lex.next();
-        if self.current != Some(Token::STRINGLITERAL) {
-            Err("No String found.")
+        if self.current != Some(Token::WORDNAME) {
+            return Err(ParseError::MissingVariableName { span: self.span() });
+        }
+        let name = word_name(self.lex.slice());
+        if self.is_defined(&name) {
+            self.errors.push(ParseError::DuplicateWord { name: name.clone(), span: self.span() });
+        }
+        self.current = self.lex.next();
+        let cell = self.memory_size;
+        self.cells.insert(name.clone(), cell);
+        self.memory_size += size;
+        Ok(Expr::DeclareVariable { name, cell })
+    }
+
+    /// ! stores the second value on the stack at the address on top, and @ replaces the
+    /// address on top with the value stored there.
+    fn parse_memory_word(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        if self.current == Some(Token::STORE) {
+            self.check_depth(2, "!", &span)?;
+            self.variables.truncate(self.variables.len() - 2);
+            self.current = self.lex.next();
+            Ok(Expr::Store { span })
         } else {
-            let the_string = self.lex.slice().to_string() + &*" ";
+            self.check_depth(1, "@", &span)?;
+            self.variables.pop();
+            self.variables.push(Box::new(Expr::Fetch { span: span.clone() }));
             self.current = self.lex.next();
-            if self.current != Some(Token::STRINGEND) {
-                Err("String was not ended properly.")
-            } else {
-                self.current = self.lex.next();
-                if self.stack.len() > 0 {
-                    let call = Expr::Call {
-                        other: self.stack.pop().unwrap(),
-                        actual: Box::new(Expr::StringPrint(Box::new(the_string)))
-                    };
-                    self.stack.push(Box::from(call.clone()));
-                    Ok(call)
-                } else {
-                    self.stack.push(Box::new(Expr::StringPrint(Box::new(the_string.clone()))));
-                    Ok(Expr::StringPrint(Box::new(the_string)))
+            Ok(Expr::Fetch { span })
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.words.contains_key(name) || self.cells.contains_key(name)
+    }
+
+    /// Calling a word replaces the values it takes with the values it leaves. A variable's
+    /// name is looked up the same way, and pushes the address of its cell.
+    fn parse_word_call(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let name = word_name(self.lex.slice());
+        self.current = self.lex.next();
+        if let Some(cell) = self.cells.get(&name) {
+            let address = Expr::VariableAddress { name, cell: *cell };
+            self.variables.push(Box::new(address.clone()));
+            return Ok(address);
+        }
+        let effect = match self.words.get(&name) {
+            Some(effect) => *effect,
+            None => return Err(ParseError::UndefinedWord { name, span })
+        };
+        if self.defining.as_ref() == Some(&name) {
+            self.recursed = true;
+        }
+        let effect = match effect {
+            Some(effect) => effect,
+            None => {
+                // The word may take and replace any of the values below it.
+                self.dynamic_depth = true;
+                self.variables.iter_mut().for_each(|value| *value = Box::new(Expr::Stacked));
+                return Ok(Expr::CallWord { name, span });
+            }
+        };
+        if self.variables.len() < effect.inputs && !self.dynamic_depth {
+            return Err(ParseError::WordUnderflow { name, inputs: effect.inputs, span });
+        }
+        self.reach(effect.inputs);
+        let depth = self.variables.len() - effect.inputs;
+        self.variables.truncate(depth);
+        self.variables.resize(depth + effect.outputs, Box::new(Expr::Stacked));
+        Ok(Expr::CallWord { name, span })
+    }
+
+    /// Replaces every value that differs from the one at the same place in `entry` with
+    /// `Stacked`, or all of them once the depth is dynamic. Only values that are the same
+    /// whichever branch ran, or however many times a loop ran, are known after it.
+    fn forget_changed(&mut self, entry: &[Box<Expr>]) {
+        let dynamic = self.dynamic_depth;
+        for (index, value) in self.variables.iter_mut().enumerate() {
+            if dynamic || entry.get(index) != Some(value) {
+                *value = Box::new(Expr::Stacked);
+            }
+        }
+    }
+
+    fn check_loop_depth(&mut self, expected: usize) -> bool {
+        self.dynamic_depth |= self.variables.len() != expected;
+        self.dynamic_depth
+    }
+
+    /// Numbers are written most significant digit first: レム adds one, and エミリア,
+    /// パック and ロズワール shift what has been written so far by one, two or three
+    /// decimal places. An overflowing literal is still read up to its ラム so that the
+    /// error covers all of it and parsing resumes after it.
+    ///
+    /// A number with a ・ in it is a float. The digits are read as one integer either way,
+    /// counting the places after the point, and a float is then made from their decimal
+    /// form so it is rounded only once.
+    fn parse_nb_expr(&mut self) -> Result<Expr, ParseError> {
+        let start = self.span().start;
+        let mut end = start;
+        let largest = i128::MAX >> (128 - self.cell_bits);
+        let mut n = Some(0i128);
+        let mut places: Option<u32> = None;
+        loop {
+            let shift = match self.current {
+                Some(Token::ONE) => {
+                    n = n.and_then(|n| n.checked_add(1));
+                    0
+                },
+                Some(Token::TEN) => 1,
+                Some(Token::HUNDRED) => 2,
+                Some(Token::THOUSAND) => 3,
+                Some(Token::POINT) if places.is_none() => {
+                    places = Some(0);
+                    1
+                },
+                Some(Token::POINT) => return Err(ParseError::UnexpectedToken { span: self.span() }),
+                Some(Token::FINALIZER) => break,
+                _ => return Err(ParseError::MissingFinalizer { span: start..end })
+            };
+            n = n.and_then(|n| n.checked_mul(10i128.pow(shift)));
+            places = places.map(|places| places + shift);
+            end = self.span().end;
+            self.current = self.lex.next();
+        }
+        let span = start..self.span().end;
+        self.current = self.lex.next();
+        match places {
+            Some(places) => n.map(|n| Expr::Float(format!("{}e-{}", n, places).parse().expect("The digits form a valid float.")))
+                .ok_or(ParseError::FloatOverflow { span }),
+            None => n.filter(|n| *n <= largest)
+                .map(|n| Expr::Number(Box::new(Val { n })))
+                .ok_or(ParseError::NumberOverflow { bits: self.cell_bits, span })
+        }
+    }
+
+    /// The float stack is always kept in memory, so running out of floats is caught at
+    /// runtime. Only S>F takes anything from the stack of cells.
+    fn parse_float_word(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let expr = match self.current.as_ref() {
+            Some(Token::CELLTOFLOAT) => {
+                self.check_depth(1, "S>F", &span)?;
+                self.variables.pop();
+                Expr::CellToFloat
+            },
+            Some(Token::FLOATTOCELL) => {
+                self.variables.push(Box::new(Expr::FloatToCell));
+                Expr::FloatToCell
+            },
+            token => match token.and_then(float_word) {
+                Some(word) => Expr::FloatWord(word),
+                None => return Err(ParseError::UnexpectedToken { span })
+            }
+        };
+        self.current = self.lex.next();
+        Ok(expr)
+    }
+
+    fn parse_string_expr(&mut self) -> Result<Expr, ParseError> {
+        let the_string = self.read_string()?;
+        if self.stack.len() > 0 {
+            let call = Expr::Call {
+                other: self.stack.pop().unwrap(),
+                actual: Box::new(Expr::StringPrint(Box::new(the_string)))
+            };
+            self.stack.push(Box::from(call.clone()));
+            Ok(call)
+        } else {
+            self.stack.push(Box::new(Expr::StringPrint(Box::new(the_string.clone()))));
+            Ok(Expr::StringPrint(Box::new(the_string)))
+        }
+    }
+
+    /// Reads everything up to the closing 君 with a lexer of its own, since the inside of
+    /// a string may contain characters that mean something else to the main lexer.
+    fn read_string(&mut self) -> Result<String, ParseError> {
+        let opening = self.span();
+        let mut string_lex = self.lex.clone().morph::<StringPart>();
+        let mut the_string = String::new();
+        let mut error = None;
+        loop {
+            match string_lex.next() {
+                Some(StringPart::STRINGLITERAL) => the_string.push_str(string_lex.slice()),
+                Some(StringPart::NEWLINE) => the_string.push('\n'),
+                Some(StringPart::TAB) => the_string.push('\t'),
+                Some(StringPart::ESCAPEDEND) => the_string.push('君'),
+                Some(StringPart::BACKSLASH) => the_string.push('\\'),
+                Some(StringPart::STRINGEND) => break,
+                Some(StringPart::Error) => {
+                    let escaped = string_lex.remainder().chars().next().map_or(0, char::len_utf8);
+                    string_lex.bump(escaped);
+                    error.get_or_insert(ParseError::UnknownEscape { span: self.shifted(string_lex.span()) });
+                },
+                None => {
+                    self.lex = string_lex.morph();
+                    self.current = None;
+                    return Err(ParseError::UnterminatedString { span: opening });
                 }
             }
         }
+        self.lex = string_lex.morph();
+        self.current = self.lex.next();
+        match error {
+            Some(err) => Err(err),
+            None => Ok(the_string)
+        }
+    }
+
+    /// Reads the file an INCLUDE names, relative to the file the INCLUDE is in, and parses
+    /// it as if it were written in place of the INCLUDE. A file that has already been
+    /// included is skipped, so libraries can include what they need without it being
+    /// defined twice, but a file that ends up including itself is reported.
+    fn parse_include(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        self.current = self.lex.next();
+        if self.current != Some(Token::STRINGSTART) {
+            return Err(ParseError::MissingIncludePath { span: self.span() });
+        }
+        let name = self.read_string()?;
+        let including = Path::new(&self.sources.file(span.start).path);
+        let path = including.parent().unwrap_or_else(|| Path::new("")).join(&name);
+        let shown = path.display().to_string();
+        let source = std::fs::read_to_string(&path).map_err(|err| {
+            ParseError::IncludeFailed { path: shown.clone(), reason: err.to_string(), span: span.clone() }
+        })?;
+        let file = canonical(&path);
+        if self.including.contains(&file) {
+            return Err(ParseError::IncludeCycle { path: shown, span });
+        }
+        if self.included.contains(&file) {
+            return Ok(Expr::Include { path: shown });
+        }
+
+        // The source has to live as long as the spans and names taken from it, which is
+        // the rest of the compilation.
+        let source: &'a str = Box::leak(source.into_boxed_str());
+        let offset = self.sources.add(&shown, source);
+        let mut lex = Token::lexer(source);
+        let current = lex.next();
+        let outer_lex = std::mem::replace(&mut self.lex, lex);
+        let outer_current = std::mem::replace(&mut self.current, current);
+        let outer_offset = std::mem::replace(&mut self.offset, offset);
+        self.including.push(file.clone());
+        while !self.is_finished() {
+            self.parse_expr_recovering();
+        }
+        self.including.pop();
+        self.included.push(file);
+        self.lex = outer_lex;
+        self.current = outer_current;
+        self.offset = outer_offset;
+        Ok(Expr::Include { path: shown })
+    }
+
+    fn parse_input_word(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let (expr, name, needed, pushed) = match self.current {
+            Some(Token::KEY) => (Expr::Key, "KEY", 0, 1),
+            Some(Token::ACCEPT) => (Expr::Accept { span: span.clone() }, "ACCEPT", 2, 1),
+            Some(Token::READNUMBER) => (Expr::ReadNumber, "READ-NUMBER", 0, 2),
+            Some(Token::ARGC) => (Expr::ArgCount, "ARGC", 0, 1),
+            Some(Token::ARG) => (Expr::Argument { span: span.clone() }, "ARG", 3, 1),
+            Some(Token::ARGNUMBER) => (Expr::ArgumentNumber, "ARG>NUMBER", 1, 2),
+            Some(Token::ENV) => {
+                self.current = self.lex.next();
+                if self.current != Some(Token::STRINGSTART) {
+                    return Err(ParseError::MissingEnvironmentName { span: self.span() });
+                }
+                let name = self.read_string()?;
+                (Expr::Environment { name, span: span.clone() }, "ENV", 2, 1)
+            },
+            _ => return Err(ParseError::UnexpectedToken { span })
+        };
+        self.check_depth(needed, name, &span)?;
+        self.variables.truncate(self.variables.len() - needed);
+        for _ in 0..pushed {
+            self.variables.push(Box::new(expr.clone()));
+        }
+        // Reading the name of an environment variable has already moved past its 君.
+        if !matches!(expr, Expr::Environment { .. }) {
+            self.current = self.lex.next();
+        }
+        Ok(expr)
+    }
+
+    fn parse_output_word(&mut self) -> Result<Expr, ParseError> {
+        let span = self.span();
+        let (expr, name) = match self.current {
+            Some(Token::DOT) => (Expr::PrintTop, "."),
+            Some(Token::EMIT) => (Expr::Emit, "EMIT"),
+            Some(Token::CR) => (Expr::NewLine, "CR"),
+            _ => return Err(ParseError::UnexpectedToken { span })
+        };
+        if expr != Expr::NewLine {
+            self.check_depth(1, name, &span)?;
+            self.variables.pop();
+        }
+        self.current = self.lex.next();
+        Ok(expr)
     }
 
-    fn parse_print_stack_expr(&mut self) -> Result<Expr, &'static str> {
+    fn parse_print_stack_expr(&mut self) -> Result<Expr, ParseError> {
         self.current = self.lex.next();
         if self.stack.len() > 0 {
             let call = Expr::Call {
@@ -164,6 +1165,68 @@ impl<'a> Parser<'a> {
     }
 }
 
+fn binary_operator(token: &Token) -> Option<(BinaryOp, &'static str)> {
+    match token {
+        Token::PLUS => Some((BinaryOp::Add, "+")),
+        Token::MINUS => Some((BinaryOp::Sub, "-")),
+        Token::MULT => Some((BinaryOp::Mul, "*")),
+        Token::DIV => Some((BinaryOp::Div, "/")),
+        Token::EQUAL => Some((BinaryOp::Equal, "=")),
+        Token::NOTEQUAL => Some((BinaryOp::NotEqual, "<>")),
+        Token::LESS => Some((BinaryOp::Less, "<")),
+        Token::GREATER => Some((BinaryOp::Greater, ">")),
+        Token::LESSEQUAL => Some((BinaryOp::LessEqual, "<=")),
+        Token::GREATEREQUAL => Some((BinaryOp::GreaterEqual, ">=")),
+        Token::AND => Some((BinaryOp::And, "AND")),
+        Token::OR => Some((BinaryOp::Or, "OR")),
+        Token::XOR => Some((BinaryOp::Xor, "XOR")),
+        Token::MOD => Some((BinaryOp::Mod, "MOD")),
+        Token::MIN => Some((BinaryOp::Min, "MIN")),
+        Token::MAX => Some((BinaryOp::Max, "MAX")),
+        Token::LSHIFT => Some((BinaryOp::LShift, "LSHIFT")),
+        Token::RSHIFT => Some((BinaryOp::RShift, "RSHIFT")),
+        _ => None
+    }
+}
+
+fn unary_operator(token: &Token) -> Option<(UnaryOp, &'static str)> {
+    match token {
+        Token::ZEROEQUAL => Some((UnaryOp::ZeroEqual, "0=")),
+        Token::ZEROLESS => Some((UnaryOp::ZeroLess, "0<")),
+        Token::ZEROGREATER => Some((UnaryOp::ZeroGreater, "0>")),
+        Token::INVERT => Some((UnaryOp::Invert, "INVERT")),
+        Token::NEGATE => Some((UnaryOp::Negate, "NEGATE")),
+        Token::ABS => Some((UnaryOp::Abs, "ABS")),
+        _ => None
+    }
+}
+
+fn float_word(token: &Token) -> Option<FloatWord> {
+    match token {
+        Token::FPLUS => Some(FloatWord::Add),
+        Token::FMINUS => Some(FloatWord::Sub),
+        Token::FMULT => Some(FloatWord::Mul),
+        Token::FDIV => Some(FloatWord::Div),
+        Token::FNEGATE => Some(FloatWord::Negate),
+        Token::FPRINT => Some(FloatWord::Print),
+        Token::FDUP => Some(FloatWord::Dup),
+        Token::FDROP => Some(FloatWord::Drop),
+        Token::FSWAP => Some(FloatWord::Swap),
+        Token::FOVER => Some(FloatWord::Over),
+        _ => None
+    }
+}
+
+/// Includes are told apart by where they really are, so one file reached through two
+/// different relative paths is still the same file.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn word_name(slice: &str) -> String {
+    slice.trim_start_matches('【').trim_end_matches('】').to_string()
+}
+
 #[cfg(test)]
 #[allow(unused_must_use)]
 mod tests {
@@ -173,26 +1236,105 @@ mod tests {
     fn parse_nb_expr_parses_a_number() {
         let lex = Token::lexer("レムラム");
         let mut parse: Parser = Parser::new(lex);
-        assert_eq!(parse.parse_nb_expr().unwrap().n, 1)
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 1 })))
     }
 
     #[test]
     fn parse_nb_expr_parses_a_larger_number() {
         let lex = Token::lexer("レムレムレムレムラム");
         let mut parse: Parser = Parser::new(lex);
-        assert_eq!(parse.parse_nb_expr().unwrap().n, 4)
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 4 })))
     }
 
     #[test]
-    fn parse_binary_expr_parses_plus_operation() {
-        let lex = Token::lexer("レムレムラムレムレムラム+");
+    fn parse_nb_expr_parses_shifted_digits() {
+        let lex = Token::lexer("レムエミリアレムレムラム");
         let mut parse: Parser = Parser::new(lex);
-        parse.parse_expr();
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 12 })))
+    }
+
+    #[test]
+    fn parse_nb_expr_parses_a_compact_large_number() {
+        let lex = Token::lexer("レムレムロズワールレムレムレムパックレムエミリアレムレムレムレムラム");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 2_003_014 })))
+    }
+
+    #[test]
+    fn parse_nb_expr_reports_numbers_that_overflow() {
+        let lex = Token::lexer("レムレムレムロズワールロズワールロズワールラム");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::NumberOverflow { bits: 32, span: 0..69 });
+    }
+
+    #[test]
+    fn parse_nb_expr_follows_the_cell_width() {
+        let lex = Token::lexer("レムレムレムロズワールロズワールロズワールラム");
+        let mut parse: Parser = Parser::new(lex).with_cell_bits(64);
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 3_000_000_000 })))
+    }
+
+    #[test]
+    fn parse_nb_expr_parses_a_long_unary_number() {
+        let code = "レム".repeat(100_000) + "ラム";
+        let lex = Token::lexer(&code);
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Number(Box::new(Val { n: 100_000 })))
+    }
+
+    #[test]
+    fn parse_nb_expr_parses_floats() {
+        let lex = Token::lexer("レム・レムレムレムレムレムラム ・エミリアレムレムレムレムレムラム レム・ラム");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Float(1.5));
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Float(0.05));
+        assert_eq!(parse.parse_nb_expr().unwrap(), Expr::Float(1.0));
+    }
+
+    #[test]
+    fn parse_expr_keeps_floats_apart_from_cells() {
+        let lex = Token::lexer("レムラム S>F レム・レムラム F+ F>S レムラム + F.");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack.len(), 6);
+        assert_eq!(*parse.stack[2], Expr::Float(1.1));
+        assert_eq!(*parse.stack[3], Expr::FloatWord(FloatWord::Add));
+    }
+
+    #[test]
+    fn parse_float_word_checks_the_cell_it_converts() {
+        let lex = Token::lexer("S>F");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::StackUnderflow { op: "S>F", span: 0..3 });
+    }
+
+    #[test]
+    fn parse_nb_expr_reports_a_missing_finalizer_at_the_end_of_the_program() {
+        let lex = Token::lexer("レムラムレムレム");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::MissingFinalizer { span: 12..24 });
+        assert!(parse.is_finished());
+    }
+
+    #[test]
+    fn parse_program_resumes_after_an_overflowing_number() {
+        let lex = Token::lexer("レムロズワールロズワールロズワールロズワールラムレムラム+");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program().len(), 2);
+    }
+
+    #[test]
+    fn parse_binary_expr_parses_plus_operation() {
+        let lex = Token::lexer("レムレムラムレムレムラム+");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
         parse.parse_expr();
         assert_eq!(parse.parse_expr().unwrap(), Expr::Binary {
-            op: '+',
+            op: BinaryOp::Add,
             left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-            right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 36..37
         })
     }
 
@@ -203,9 +1345,10 @@ mod tests {
         parse.parse_expr();
         parse.parse_expr();
         assert_eq!(parse.parse_binary_expr().unwrap(), Expr::Binary {
-            op: '-',
+            op: BinaryOp::Sub,
             left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-            right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 36..37
         })
     }
 
@@ -216,9 +1359,10 @@ mod tests {
         parse.parse_expr();
         parse.parse_expr();
         assert_eq!(parse.parse_binary_expr().unwrap(), Expr::Binary {
-            op: '*',
+            op: BinaryOp::Mul,
             left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-            right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 36..37
         })
     }
 
@@ -229,12 +1373,75 @@ mod tests {
         parse.parse_expr();
         parse.parse_expr();
         assert_eq!(parse.parse_binary_expr().unwrap(), Expr::Binary {
-            op: '/',
+            op: BinaryOp::Div,
             left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-            right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 36..37
+        })
+    }
+
+    #[test]
+    fn parse_binary_expr_parses_comparisons() {
+        let lex = Token::lexer("レムラム レムレムラム <>");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap(), Expr::Binary {
+            op: BinaryOp::NotEqual,
+            left: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 32..34
         })
     }
 
+    #[test]
+    fn parse_unary_expr_parses_a_test_against_zero() {
+        let lex = Token::lexer("レムラム 0= INVERT");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack, vec![Box::new(Expr::Unary {
+            op: UnaryOp::Invert,
+            operand: Box::new(Expr::Unary {
+                op: UnaryOp::ZeroEqual,
+                operand: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+                span: 13..15
+            }),
+            span: 16..22
+        })]);
+    }
+
+    #[test]
+    fn parse_expr_parses_div_mod_as_two_results() {
+        let lex = Token::lexer("レムレムラム レムラム /MOD +");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[1], Box::new(Expr::Binary {
+            op: BinaryOp::Add,
+            left: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+            right: Box::new(Expr::DivMod { span: 32..36 }),
+            span: 37..38
+        }));
+        assert_eq!(parse.variables.len(), 1);
+    }
+
+    #[test]
+    fn parse_binary_expr_reports_division_by_a_literal_zero() {
+        let lex = Token::lexer("レムラム エミリアラム / レムラム エミリアラム MOD レムラム エミリアラム /MOD");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::DivisionByZero { span: 32..33 },
+            ParseError::DivisionByZero { span: 66..69 },
+            ParseError::DivisionByZero { span: 102..106 }
+        ]);
+    }
+
+    #[test]
+    fn parse_unary_expr_reports_an_empty_stack() {
+        let lex = Token::lexer("0<");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::StackUnderflow { op: "0<", span: 0..2 }]);
+    }
+
     #[test]
     fn parse_binary_expr_parses_multiple_operations() {
         let lex = Token::lexer("レムレムラムレムレムラム+レムラム-");
@@ -242,22 +1449,44 @@ mod tests {
         parse.parse_expr();
         parse.parse_expr();
         assert_eq!(parse.parse_expr().unwrap(), Expr::Binary {
-            op: '+',
+            op: BinaryOp::Add,
             left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-            right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            span: 36..37
         });
         parse.parse_expr();
         assert_eq!(parse.parse_expr().unwrap(), Expr::Binary {
-            op: '-',
+            op: BinaryOp::Sub,
             left: Box::new(Expr::Binary {
-                op: '+',
+                op: BinaryOp::Add,
                 left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-                right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+                right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+                span: 36..37
             }),
-            right: Box::new(Expr::Number(Box::new(Val { n: 1 })))
+            right: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+            span: 49..50
         });
     }
 
+    #[test]
+    fn parse_expr_pops_the_exit_code_for_bye() {
+        let lex = Token::lexer("レムラム BYE BYE");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::StackUnderflow { op: "BYE", span: 17..20 }]);
+        assert_eq!(*parse.stack[1], Expr::Bye);
+    }
+
+    #[test]
+    fn parse_output_word_pops_what_it_prints() {
+        let lex = Token::lexer("レムラム レムレムラム . CR EMIT .");
+        let mut parse: Parser = Parser::new(lex);
+        let errors = parse.parse_program();
+        assert_eq!(errors, vec![ParseError::StackUnderflow { op: ".", span: 42..43 }]);
+        assert_eq!(*parse.stack[2], Expr::PrintTop);
+        assert_eq!(*parse.stack[3], Expr::NewLine);
+        assert_eq!(*parse.stack[4], Expr::Emit);
+    }
+
     #[test]
     fn parse_printstack_expr_parses_expression() {
         let lex = Token::lexer("ベティ");
@@ -269,7 +1498,33 @@ mod tests {
     fn parse_stringprint_expr_parses_expression() {
         let lex = Token::lexer("スバルtest君");
         let mut parse: Parser = Parser::new(lex);
-        assert_eq!(parse.parse_expr().unwrap(), Expr::StringPrint(Box::new("test ".to_string())));
+        assert_eq!(parse.parse_expr().unwrap(), Expr::StringPrint(Box::new("test".to_string())));
+    }
+
+    #[test]
+    fn parse_stringprint_expr_parses_unicode_and_escapes() {
+        let lex = Token::lexer("スバルレムは 1 番!\\n\\t\\君\\\\君ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap(), Expr::StringPrint(Box::new("レムは 1 番!\n\t君\\".to_string())));
+        assert_eq!(parse.parse_expr().unwrap(), Expr::Call {
+            other: Box::new(Expr::StringPrint(Box::new("レムは 1 番!\n\t君\\".to_string()))),
+            actual: Box::new(Expr::PrintStack)
+        });
+    }
+
+    #[test]
+    fn parse_stringprint_expr_parses_an_empty_string() {
+        let lex = Token::lexer("スバル君");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap(), Expr::StringPrint(Box::new(String::new())));
+    }
+
+    #[test]
+    fn parse_stringprint_expr_reports_unknown_escapes() {
+        let lex = Token::lexer("スバルa\\qb君ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::UnknownEscape { span: 10..12 });
+        assert_eq!(parse.parse_expr().unwrap(), Expr::PrintStack);
     }
 
     #[test]
@@ -288,12 +1543,580 @@ mod tests {
         parse.parse_expr();
         assert_eq!(parse.parse_expr().unwrap(), Expr::Call {
             other:  Box::new(Expr::Binary {
-                op: '/',
+                op: BinaryOp::Div,
                 left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
-                right: Box::new(Expr::Number(Box::new(Val { n: 2 })))
+                right: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+                span: 36..37
             }),
             actual: Box::new(Expr::PrintStack)
         })
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn parse_binary_expr_reports_the_span_of_the_operator() {
+        let lex = Token::lexer("レムラム+");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::StackUnderflow { op: "+", span: 12..13 });
+    }
+
+    #[test]
+    fn parse_nb_expr_reports_a_missing_finalizer() {
+        let lex = Token::lexer("レムレムベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::MissingFinalizer { span: 0..12 });
+    }
+
+    #[test]
+    fn parse_string_expr_reports_an_unterminated_string() {
+        let lex = Token::lexer("レムラムスバルtestラム");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::UnterminatedString { span: 12..21 });
+        assert!(parse.is_finished());
+    }
+
+    #[test]
+    fn parse_program_reports_every_error() {
+        let lex = Token::lexer("+レムラム君レムレムベティレムラム-");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::StackUnderflow { op: "+", span: 0..1 },
+            ParseError::UnexpectedToken { span: 13..16 },
+            ParseError::MissingFinalizer { span: 16..28 }
+        ]);
+        assert!(parse.is_finished());
+    }
+
+    #[test]
+    fn parse_program_keeps_parsing_after_an_error() {
+        let lex = Token::lexer("君レムラムレムラム+");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program().len(), 1);
+        assert_eq!(parse.stack.len(), 1);
+    }
+
+    #[test]
+    fn parse_program_stops_at_program_end() {
+        let lex = Token::lexer("レムラムさよなら君");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack.len(), 2);
+    }
+
+    #[test]
+    fn parse_expr_reports_unknown_characters() {
+        let lex = Token::lexer("#");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::Lex(LexError::UnknownCharacter {
+            ch: '#',
+            span: 0..1,
+            suggestion: None
+        }));
+    }
+
+    #[test]
+    fn parse_expr_suggests_the_keyword_a_near_miss_was_meant_to_be() {
+        let lex = Token::lexer("レムラムスバtest君");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::Lex(LexError::UnknownCharacter {
+            ch: 'ス',
+            span: 12..18,
+            suggestion: Some("スバル")
+        }));
+    }
+
+    #[test]
+    fn parse_program_reports_a_near_miss_once() {
+        let lex = Token::lexer("レムラムレムラム＋ベテ");
+        let mut parse: Parser = Parser::new(lex);
+        let errors = parse.parse_program();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].span(), 24..27);
+        assert_eq!(errors[1].span(), 27..33);
+    }
+
+    #[test]
+    fn parse_program_skips_comments() {
+        let lex = Token::lexer("※足し算\nレムラム（一つ目（入れ子））レムラム+ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack.len(), 1);
+        assert_eq!(parse.comments(), &[0..12, 25..55]);
+    }
+
+    #[test]
+    fn parse_program_reports_an_unterminated_comment() {
+        let lex = Token::lexer("レムラム（ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::Lex(LexError::UnterminatedComment { span: 12..24 })
+        ]);
+    }
+
+    #[test]
+    fn parse_stack_word_keeps_the_word_in_the_program() {
+        let lex = Token::lexer("レムラムレムレムラムswap-");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap(), Expr::Swap);
+        assert_eq!(parse.parse_expr().unwrap(), Expr::Binary {
+            op: BinaryOp::Sub,
+            left: Box::new(Expr::Number(Box::new(Val { n: 2 }))),
+            right: Box::new(Expr::Swap),
+            span: 34..35
+        });
+        assert_eq!(parse.stack.len(), 2);
+    }
+
+    #[test]
+    fn parse_stack_word_models_the_stack_depth() {
+        let lex = Token::lexer("レムラムDUP DUP + DUP OVER TUCK ROT NIP DROP + +");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+
+        let lex = Token::lexer("レムラムDROP DUP");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::StackUnderflow { op: "DUP", span: 17..20 }]);
+    }
+
+    #[test]
+    fn parse_stack_word_reports_underflow_by_name() {
+        let lex = Token::lexer("レムラムレムラムrot");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::StackUnderflow { op: "ROT", span: 24..27 }]);
+    }
+
+    #[test]
+    fn parse_expr_reports_unknown_words() {
+        let lex = Token::lexer("dupe");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::Lex(LexError::UnknownWord {
+            word: "dupe".to_string(),
+            span: 0..4
+        }));
+        assert!(parse.is_finished());
+    }
+
+    #[test]
+    fn parse_if_expr_parses_both_branches() {
+        let lex = Token::lexer("レムラムレムラム IF レムレムラム ELSE レムレムレムラム THEN +");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack, vec![
+            Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+            Box::new(Expr::Binary {
+                op: BinaryOp::Add,
+                left: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+                right: Box::new(Expr::If {
+                    then_branch: vec![Box::new(Expr::Number(Box::new(Val { n: 2 })))],
+                    else_branch: vec![Box::new(Expr::Number(Box::new(Val { n: 3 })))],
+                    span: 25..27,
+                    dynamic: false
+                }),
+                span: 82..83
+            })
+        ]);
+    }
+
+    #[test]
+    fn parse_if_expr_uses_operands_from_before_the_if() {
+        let lex = Token::lexer("レムラムレムラム IF レムラム + THEN");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap(), Expr::If {
+            then_branch: vec![Box::new(Expr::Binary {
+                op: BinaryOp::Add,
+                left: Box::new(Expr::Stacked),
+                right: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+                span: 41..42
+            })],
+            else_branch: vec![],
+            span: 25..27,
+            dynamic: false
+        });
+    }
+
+    #[test]
+    fn parse_if_expr_leaves_unbalanced_branches_to_the_runtime() {
+        let lex = Token::lexer("レムラムレムラム IF DROP THEN DROP DROP");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[2], Box::new(Expr::If {
+            then_branch: vec![Box::new(Expr::Drop)],
+            else_branch: vec![],
+            span: 25..27,
+            dynamic: true
+        }));
+    }
+
+    #[test]
+    fn parse_if_expr_forgets_values_the_branches_leave_differently() {
+        let lex = Token::lexer("レムレムラム レムラム KEY IF DROP エミリアラム THEN /");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.variables.len(), 1);
+    }
+
+    #[test]
+    fn parse_if_expr_keeps_values_both_branches_leave_alone() {
+        let lex = Token::lexer("レムラム エミリアラム KEY IF DUP DROP THEN /");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::DivisionByZero { span: 53..54 }]);
+    }
+
+    #[test]
+    fn parse_if_expr_reports_a_missing_then() {
+        let lex = Token::lexer("レムラム IF レムラム");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::UnterminatedIf { span: 13..15 }]);
+    }
+
+    #[test]
+    fn parse_if_expr_keeps_parsing_after_an_error_in_a_branch() {
+        let lex = Token::lexer("レムラム IF + ELSE 君 THEN ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::StackUnderflow { op: "+", span: 16..17 },
+            ParseError::UnexpectedToken { span: 23..26 }
+        ]);
+        assert_eq!(parse.stack.len(), 2);
+    }
+
+    #[test]
+    fn parse_begin_expr_parses_until_loops() {
+        let lex = Token::lexer("レムラム BEGIN DUP UNTIL");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_expr();
+        assert_eq!(parse.parse_expr().unwrap(), Expr::BeginUntil {
+            body: vec![Box::new(Expr::Dup)],
+            span: 13..18,
+            dynamic: false
+        });
+        assert!(parse.errors.is_empty());
+    }
+
+    #[test]
+    fn parse_begin_expr_parses_while_loops() {
+        let lex = Token::lexer("レムラム BEGIN DUP WHILE レムラム - REPEAT ベティ");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[1], Box::new(Expr::Call {
+            other: Box::new(Expr::BeginWhile {
+                condition: vec![Box::new(Expr::Dup)],
+                body: vec![Box::new(Expr::Binary {
+                    op: BinaryOp::Sub,
+                    left: Box::new(Expr::Stacked),
+                    right: Box::new(Expr::Number(Box::new(Val { n: 1 }))),
+                    span: 42..43
+                })],
+                span: 13..18,
+                dynamic: false
+            }),
+            actual: Box::new(Expr::PrintStack)
+        }));
+    }
+
+    #[test]
+    fn parse_begin_expr_leaves_loops_that_change_the_stack_depth_to_the_runtime() {
+        let lex = Token::lexer("レムラム BEGIN DUP DUP UNTIL");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[1], Box::new(Expr::BeginUntil {
+            body: vec![Box::new(Expr::Dup), Box::new(Expr::Dup)],
+            span: 13..18,
+            dynamic: true
+        }));
+
+        let lex = Token::lexer("レムラム BEGIN DUP WHILE レムラム REPEAT + + +");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+    }
+
+    #[test]
+    fn parse_begin_expr_forgets_values_the_body_changes() {
+        let lex = Token::lexer("エミリアラム BEGIN DUP 0= WHILE DROP レムラム REPEAT レムエミリアラム SWAP /");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+
+        let lex = Token::lexer("エミリアラム レムラム BEGIN SWAP DUP UNTIL レムエミリアラム SWAP /");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+
+        let code = "エミリアラム BEGIN DUP WHILE REPEAT レムエミリアラム SWAP /";
+        let mut parse: Parser = Parser::new(Token::lexer(code));
+        assert_eq!(parse.parse_program(), vec![ParseError::DivisionByZero { span: 72..73 }]);
+    }
+
+    #[test]
+    fn parse_begin_expr_reports_a_missing_end() {
+        let lex = Token::lexer("レムラム BEGIN DUP WHILE");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::UnterminatedLoop { span: 13..18 }]);
+    }
+
+    #[test]
+    fn parse_do_expr_parses_a_counted_loop() {
+        let lex = Token::lexer("レムラム レムエミリアラム レムラム DO I + LOOP");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[3], Box::new(Expr::DoLoop {
+            body: vec![Box::new(Expr::Binary {
+                op: BinaryOp::Add,
+                left: Box::new(Expr::Stacked),
+                right: Box::new(Expr::LoopIndex),
+                span: 56..57
+            })],
+            span: 51..53,
+            dynamic: false
+        }));
+    }
+
+    #[test]
+    fn parse_do_expr_forgets_values_the_body_changes() {
+        let lex = Token::lexer("エミリアラム レムラム レムエミリアラム エミリアラム DO SWAP LOOP レムエミリアラム SWAP /");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+    }
+
+    #[test]
+    fn parse_expr_reports_i_outside_a_counted_loop() {
+        let lex = Token::lexer("I");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::IndexOutsideLoop { span: 0..1 });
+    }
+
+    #[test]
+    fn parse_definition_works_out_the_stack_effect() {
+        let lex = Token::lexer(": 【二倍】 DUP + ; レムラム 【二倍】");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.words["二倍"], Some(StackEffect { inputs: 1, outputs: 1 }));
+        assert_eq!(parse.stack[0], Box::new(Expr::Definition {
+            name: "二倍".to_string(),
+            body: vec![Box::new(Expr::Binary {
+                op: BinaryOp::Add,
+                left: Box::new(Expr::Stacked),
+                right: Box::new(Expr::Dup),
+                span: 19..20
+            })],
+            span: 0..1
+        }));
+        assert_eq!(parse.stack[2], Box::new(Expr::CallWord { name: "二倍".to_string(), span: 36..48 }));
+    }
+
+    #[test]
+    fn parse_definition_works_out_the_stack_effect_of_a_recursive_word() {
+        let lex = Token::lexer(": 【階乗】 DUP レムラム - DUP IF 【階乗】 * ELSE DROP THEN ;");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.words["階乗"], Some(StackEffect { inputs: 1, outputs: 1 }));
+    }
+
+    #[test]
+    fn parse_definition_gives_a_recursive_word_that_never_settles_no_stack_effect() {
+        let lex = Token::lexer(": 【無限】 レムラム 【無限】 ; 【無限】 + +");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.words["無限"], None);
+    }
+
+    #[test]
+    fn parse_definition_gives_a_word_with_unbalanced_branches_no_stack_effect() {
+        let lex = Token::lexer(": 【たぶん】 IF DUP THEN ;");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.words["たぶん"], None);
+        assert!(!parse.dynamic_depth);
+    }
+
+    #[test]
+    fn parse_definition_reports_definitions_inside_branches() {
+        let lex = Token::lexer("レムラム IF : 【二倍】 DUP + ; THEN");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::NestedDefinition { span: 16..17 }]);
+    }
+
+    #[test]
+    fn pragma_reads_an_option_from_a_line_comment() {
+        let lex = Token::lexer("※ overflow: checked\nレムラム ※ overflow comes later");
+        let mut parse: Parser = Parser::new(lex);
+        parse.parse_program();
+        assert_eq!(parse.pragma("overflow"), Some(("checked", 14..21)));
+        assert_eq!(parse.pragma("cell"), None);
+    }
+
+    #[test]
+    fn parse_variable_gives_every_variable_a_cell() {
+        let lex = Token::lexer("VARIABLE 【甲】 VARIABLE 【乙】 レムラム 【乙】 ! 【乙】 @");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.variable_count(), 2);
+        assert_eq!(parse.stack[1], Box::new(Expr::DeclareVariable { name: "乙".to_string(), cell: 1 }));
+        assert_eq!(parse.stack[3], Box::new(Expr::VariableAddress { name: "乙".to_string(), cell: 1 }));
+        assert_eq!(parse.stack[4], Box::new(Expr::Store { span: 61..62 }));
+        assert_eq!(parse.stack[6], Box::new(Expr::Fetch { span: 73..74 }));
+        assert_eq!(parse.variables.len(), 1);
+    }
+
+    #[test]
+    fn parse_variable_sets_aside_the_cells_of_a_buffer() {
+        let lex = Token::lexer("VARIABLE 【甲】 レムエミリアラム BUFFER: 【行】 VARIABLE 【乙】 【行】 レムエミリアラム ACCEPT READ-NUMBER KEY");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.variable_count(), 12);
+        assert_eq!(parse.stack[1], Box::new(Expr::DeclareVariable { name: "行".to_string(), cell: 1 }));
+        assert_eq!(parse.stack[2], Box::new(Expr::DeclareVariable { name: "乙".to_string(), cell: 11 }));
+        assert_eq!(parse.variables.len(), 4);
+    }
+
+    #[test]
+    fn parse_input_word_reads_arguments_and_the_environment() {
+        let lex = Token::lexer("レムエミリアラム BUFFER: 【値】 ARGC 【値】 レムエミリアラム ARG 【値】 レムエミリアラム ENV スバルHOME君 ARGC ARG>NUMBER");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+        assert_eq!(parse.stack[4], Box::new(Expr::Argument { span: 83..86 }));
+        assert_eq!(parse.stack[7], Box::new(Expr::Environment { name: "HOME".to_string(), span: 122..125 }));
+        assert_eq!(parse.stack[9], Box::new(Expr::ArgumentNumber));
+        assert_eq!(parse.variables.len(), 4);
+    }
+
+    #[test]
+    fn parse_input_word_needs_the_name_of_an_environment_variable() {
+        let lex = Token::lexer("ENV レムラム");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_expr().unwrap_err(), ParseError::MissingEnvironmentName { span: 4..10 });
+    }
+
+    #[test]
+    fn parse_variable_needs_the_size_of_a_buffer() {
+        let lex = Token::lexer("BUFFER: 【行】");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![ParseError::MissingBufferSize { span: 0..7 }]);
+    }
+
+    #[test]
+    fn parse_variable_reports_names_that_are_taken() {
+        let lex = Token::lexer(": 【甲】 ; VARIABLE 【甲】");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::DuplicateWord { name: "甲".to_string(), span: 23..32 }
+        ]);
+    }
+
+    #[test]
+    fn parse_memory_word_checks_the_stack_holds_a_value_and_an_address() {
+        let lex = Token::lexer("VARIABLE 【甲】 【甲】 !");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::StackUnderflow { op: "!", span: 29..30 }
+        ]);
+    }
+
+    #[test]
+    fn parse_word_call_reports_undefined_words() {
+        let lex = Token::lexer("【名前】");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::UndefinedWord { name: "名前".to_string(), span: 0..12 }
+        ]);
+    }
+
+    #[test]
+    fn parse_word_call_checks_the_stack_holds_the_inputs() {
+        let lex = Token::lexer(": 【二倍】 DUP + ; 【二倍】");
+        let mut parse: Parser = Parser::new(lex);
+        assert_eq!(parse.parse_program(), vec![
+            ParseError::WordUnderflow { name: "二倍".to_string(), inputs: 1, span: 23..35 }
+        ]);
+    }
+
+    #[test]
+    fn parse_word_call_forgets_the_values_a_word_with_no_stack_effect_could_change() {
+        let lex = Token::lexer(": 【z】 DUP 0= IF DROP レムラム レムラム THEN ; レムエミリアラム エミリアラム 【z】 /");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(parse.parse_program().is_empty());
+    }
+
+    /// Writes `files` into a directory of their own under the temporary directory and
+    /// returns the path of the first, so include tests do not see each other's files.
+    fn write_files(test: &str, files: &[(&str, &str)]) -> String {
+        let dir = std::env::temp_dir().join(format!("meidolang-{}-{}", test, std::process::id()));
+        for (name, source) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, source).unwrap();
+        }
+        dir.join(files[0].0).display().to_string()
+    }
+
+    #[test]
+    fn parse_include_reads_files_relative_to_the_including_file() {
+        let main = write_files("relative", &[
+            ("main.meido", "INCLUDE スバルlib/words.meido君 レムラム 【二倍】"),
+            ("lib/words.meido", "INCLUDE スバル二倍.meido君"),
+            ("lib/二倍.meido", ": 【二倍】 DUP + ;")
+        ]);
+        let source = std::fs::read_to_string(&main).unwrap();
+        let mut parse: Parser = Parser::new(Token::lexer(&source)).with_path(&main);
+        assert_eq!(parse.parse_program(), vec![]);
+        assert_eq!(parse.stack.len(), 3);
+        assert!(matches!(*parse.stack[0], Expr::Definition { .. }));
+        assert!(matches!(*parse.stack[2], Expr::CallWord { .. }));
+    }
+
+    #[test]
+    fn parse_include_points_errors_into_the_included_file() {
+        let main = write_files("errors", &[
+            ("main.meido", "INCLUDE スバルwords.meido君"),
+            ("words.meido", "レムラム\n+")
+        ]);
+        let source = std::fs::read_to_string(&main).unwrap();
+        let mut parse: Parser = Parser::new(Token::lexer(&source)).with_path(&main);
+        let errors = parse.parse_program();
+        assert_eq!(errors.len(), 1);
+        let (path, line, column) = parse.sources.locate(errors[0].span().start);
+        assert!(path.ends_with("words.meido"));
+        assert_eq!((line, column), (2, 1));
+    }
+
+    #[test]
+    fn parse_include_skips_files_already_included() {
+        let main = write_files("twice", &[
+            ("main.meido", "INCLUDE スバルa.meido君 INCLUDE スバルb.meido君"),
+            ("a.meido", "INCLUDE スバルc.meido君"),
+            ("b.meido", "INCLUDE スバルc.meido君"),
+            ("c.meido", ": 【一】 レムラム ;")
+        ]);
+        let source = std::fs::read_to_string(&main).unwrap();
+        let mut parse: Parser = Parser::new(Token::lexer(&source)).with_path(&main);
+        assert_eq!(parse.parse_program(), vec![]);
+        assert_eq!(parse.stack.len(), 1);
+    }
+
+    #[test]
+    fn parse_include_reports_cycles() {
+        let main = write_files("cycle", &[
+            ("main.meido", "INCLUDE スバルa.meido君"),
+            ("a.meido", "INCLUDE スバルmain.meido君")
+        ]);
+        let source = std::fs::read_to_string(&main).unwrap();
+        let mut parse: Parser = Parser::new(Token::lexer(&source)).with_path(&main);
+        let errors = parse.parse_program();
+        assert!(matches!(errors.as_slice(), [ParseError::IncludeCycle { .. }]));
+        let (path, line, column) = parse.sources.locate(errors[0].span().start);
+        assert!(path.ends_with("a.meido"));
+        assert_eq!((line, column), (1, 1));
+    }
+
+    #[test]
+    fn parse_include_reports_missing_files() {
+        let lex = Token::lexer("INCLUDE スバルnowhere.meido君");
+        let mut parse: Parser = Parser::new(lex);
+        assert!(matches!(parse.parse_program().as_slice(), [ParseError::IncludeFailed { span, .. }] if *span == (0..7)));
+    }
+
+}
@@ -1,27 +1,82 @@
 
-use logos::Logos;
+use logos::{Filter, Lexer, Logos, Skip, Span};
 
-#[derive(Logos, Debug, PartialEq)]
+// Both lexers record the span of every comment they skip in their extras, so tooling
+// can still find the comments after parsing.
+#[derive(Logos, Debug, PartialEq, Clone)]
+#[logos(extras = Vec<Span>)]
 pub enum Token {
     // Tokens can be literal strings, of any length.
     #[token("レム")]
     ONE,
 
+    // Shift the number written so far one, two or three decimal places,
+    // so レムエミリアレムレムラム is 12.
+    #[token("エミリア")]
+    TEN,
+
+    #[token("パック")]
+    HUNDRED,
+
+    #[token("ロズワール")]
+    THOUSAND,
+
     #[token("ラム")]
     FINALIZER,
 
+    // A number with a decimal point in it is a float. The point shifts like エミリア and
+    // every shift after it adds a decimal place, so レム・レムレムレムレムレムラム is 1.5.
+    #[token("・")]
+    POINT,
+
+    // Everything after スバル is lexed as a StringPart until the closing 君.
     #[token("スバル")]
     STRINGSTART,
 
-    #[regex("[a-zA-Z]+")]
-    STRINGLITERAL,
-
     #[token("君")]
     STRINGEND,
 
+    // Prints the whole stack without changing it, after its depth in angle brackets.
     #[token("ベティ")]
+    #[token(".s", ignore(ascii_case))]
     PRINTSTACK,
 
+    // Pops the value on top of the stack and prints it.
+    #[token(".")]
+    DOT,
+
+    #[token("cr", ignore(ascii_case))]
+    CR,
+
+    // Pops a value and prints the character with that code.
+    #[token("emit", ignore(ascii_case))]
+    EMIT,
+
+    // Reading from stdin: KEY reads a byte, ACCEPT reads a line into memory and
+    // READ-NUMBER reads a number along with a flag telling whether there was one.
+    #[token("key", ignore(ascii_case))]
+    KEY,
+
+    #[token("accept", ignore(ascii_case))]
+    ACCEPT,
+
+    #[token("read-number", ignore(ascii_case))]
+    READNUMBER,
+
+    // The program's arguments and environment. ENV is followed by the name of the
+    // environment variable as a string.
+    #[token("argc", ignore(ascii_case))]
+    ARGC,
+
+    #[token("arg", ignore(ascii_case))]
+    ARG,
+
+    #[token("arg>number", ignore(ascii_case))]
+    ARGNUMBER,
+
+    #[token("env", ignore(ascii_case))]
+    ENV,
+
     #[token("+")]
     PLUS,
 
@@ -34,9 +89,215 @@ pub enum Token {
     #[token("/")]
     DIV,
 
+    // Comparisons leave a Forth flag: -1 when they hold and 0 when they do not.
+    #[token("=")]
+    EQUAL,
+
+    #[token("<>")]
+    NOTEQUAL,
+
+    #[token("<")]
+    LESS,
+
+    #[token(">")]
+    GREATER,
+
+    #[token("<=")]
+    LESSEQUAL,
+
+    #[token(">=")]
+    GREATEREQUAL,
+
+    #[token("0=")]
+    ZEROEQUAL,
+
+    #[token("0<")]
+    ZEROLESS,
+
+    #[token("0>")]
+    ZEROGREATER,
+
+    // Bitwise, as in Forth, which makes them logical operators on flags.
+    #[token("and", ignore(ascii_case))]
+    AND,
+
+    #[token("or", ignore(ascii_case))]
+    OR,
+
+    #[token("invert", ignore(ascii_case))]
+    INVERT,
+
+    #[token("xor", ignore(ascii_case))]
+    XOR,
+
+    // Like /, MOD and /MOD round toward zero, so the remainder takes the sign of the
+    // dividend.
+    #[token("mod", ignore(ascii_case))]
+    MOD,
+
+    #[token("/mod", ignore(ascii_case))]
+    DIVMOD,
+
+    #[token("negate", ignore(ascii_case))]
+    NEGATE,
+
+    #[token("abs", ignore(ascii_case))]
+    ABS,
+
+    #[token("min", ignore(ascii_case))]
+    MIN,
+
+    #[token("max", ignore(ascii_case))]
+    MAX,
+
+    #[token("lshift", ignore(ascii_case))]
+    LSHIFT,
+
+    #[token("rshift", ignore(ascii_case))]
+    RSHIFT,
+
     #[token("さよなら")]
     PROGRAMEND,
 
+    // `INCLUDE スバル名前.meido君` reads another file in its place.
+    #[token("include", ignore(ascii_case))]
+    INCLUDE,
+
+    // Pops an exit code and ends the program with it straight away.
+    #[token("bye", ignore(ascii_case))]
+    BYE,
+
+    // Floats have a stack of their own, with its own words as in Forth.
+    #[token("f+", ignore(ascii_case))]
+    FPLUS,
+
+    #[token("f-", ignore(ascii_case))]
+    FMINUS,
+
+    #[token("f*", ignore(ascii_case))]
+    FMULT,
+
+    #[token("f/", ignore(ascii_case))]
+    FDIV,
+
+    #[token("fnegate", ignore(ascii_case))]
+    FNEGATE,
+
+    #[token("f.", ignore(ascii_case))]
+    FPRINT,
+
+    #[token("fdup", ignore(ascii_case))]
+    FDUP,
+
+    #[token("fdrop", ignore(ascii_case))]
+    FDROP,
+
+    #[token("fswap", ignore(ascii_case))]
+    FSWAP,
+
+    #[token("fover", ignore(ascii_case))]
+    FOVER,
+
+    // S>F moves a cell to the float stack, and F>S moves a float back, rounding toward zero.
+    #[token("s>f", ignore(ascii_case))]
+    CELLTOFLOAT,
+
+    #[token("f>s", ignore(ascii_case))]
+    FLOATTOCELL,
+
+    // Forth's stack words are written in ASCII and, as in gforth, ignore case.
+    #[token("dup", ignore(ascii_case))]
+    DUP,
+
+    #[token("drop", ignore(ascii_case))]
+    DROP,
+
+    #[token("swap", ignore(ascii_case))]
+    SWAP,
+
+    #[token("over", ignore(ascii_case))]
+    OVER,
+
+    #[token("rot", ignore(ascii_case))]
+    ROT,
+
+    #[token("nip", ignore(ascii_case))]
+    NIP,
+
+    #[token("tuck", ignore(ascii_case))]
+    TUCK,
+
+    #[token("if", ignore(ascii_case))]
+    IF,
+
+    #[token("else", ignore(ascii_case))]
+    ELSE,
+
+    #[token("then", ignore(ascii_case))]
+    THEN,
+
+    #[token("begin", ignore(ascii_case))]
+    BEGIN,
+
+    #[token("until", ignore(ascii_case))]
+    UNTIL,
+
+    #[token("while", ignore(ascii_case))]
+    WHILE,
+
+    #[token("repeat", ignore(ascii_case))]
+    REPEAT,
+
+    #[token("do", ignore(ascii_case))]
+    DO,
+
+    #[token("loop", ignore(ascii_case))]
+    LOOP,
+
+    #[token("i", ignore(ascii_case))]
+    I,
+
+    // Words are defined as in Forth, with `: 【名前】 … ;`, and called by their name.
+    #[token(":")]
+    COLON,
+
+    #[token(";")]
+    SEMICOLON,
+
+    // `VARIABLE 【名前】` sets aside a cell of memory. Its name pushes the cell's address,
+    // which ! stores to and @ fetches from.
+    #[token("variable", ignore(ascii_case))]
+    VARIABLE,
+
+    // `n BUFFER: 【名前】` sets aside n cells, which ACCEPT can read a line into.
+    #[token("buffer:", ignore(ascii_case))]
+    BUFFER,
+
+    #[token("!")]
+    STORE,
+
+    #[token("@")]
+    FETCH,
+
+    // The brackets let a word's name use any characters, including katakana that would
+    // otherwise be read as numbers.
+    #[regex(r"【[^【】 \t\n\f\v]+】")]
+    WORDNAME,
+
+    // Any other ASCII word, so a typo is reported once rather than letter by letter.
+    #[regex("[a-zA-Z][a-zA-Z0-9]*")]
+    UNKNOWNWORD,
+
+    // ※ starts a comment that runs to the end of the line.
+    #[regex("※[^\n]*", line_comment)]
+    LINECOMMENT,
+
+    // （ starts a comment that runs to its matching ）, so comments can nest.
+    // The callback skips finished comments, so the parser only ever sees this
+    // token when a comment is never closed.
+    #[token("（", block_comment)]
+    UNTERMINATEDCOMMENT,
+
     // Logos requires one token variant to handle errors,
     // it can be named anything you wish.
     #[error]
@@ -44,4 +305,351 @@ pub enum Token {
     // or any other matches we wish to skip.
     #[regex(r"[ \t\n\f\v]+", logos::skip)]
     Error,
-}
\ No newline at end of file
+}
+
+/// The inside of a string literal. The parser morphs its lexer into this one after
+/// `スバル` and back again after the closing `君`.
+#[derive(Logos, Debug, PartialEq)]
+#[logos(extras = Vec<Span>)]
+pub enum StringPart {
+    #[regex(r"[^君\\]+")]
+    STRINGLITERAL,
+
+    #[token(r"\n")]
+    NEWLINE,
+
+    #[token(r"\t")]
+    TAB,
+
+    #[token(r"\君")]
+    ESCAPEDEND,
+
+    #[token(r"\\")]
+    BACKSLASH,
+
+    #[token("君")]
+    STRINGEND,
+
+    // A backslash that does not start one of the escapes above.
+    #[error]
+    Error,
+}
+
+fn line_comment(lex: &mut Lexer<Token>) -> Skip {
+    lex.extras.push(lex.span());
+    Skip
+}
+
+fn block_comment(lex: &mut Lexer<Token>) -> Filter<()> {
+    let mut depth = 1;
+    let mut length = 0;
+    for ch in lex.remainder().chars() {
+        length += ch.len_utf8();
+        match ch {
+            '（' => depth += 1,
+            '）' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            lex.bump(length);
+            lex.extras.push(lex.span());
+            return Filter::Skip;
+        }
+    }
+    lex.bump(length);
+    Filter::Emit(())
+}
+
+const KEYWORDS: &[&str] = &[
+    "レム", "エミリア", "パック", "ロズワール", "ラム", "・", "スバル", "君", "ベティ", ".", "+", "-", "*", "/", "さよなら", ":", ";", "!", "@", "=", "<", ">"
+];
+
+/// Looks for a keyword that `rest` starts to spell without finishing, such as `レ` for
+/// `レム`, or that it spells with a full-width character, such as `＋` for `+`. Returns
+/// the keyword along with how many bytes of `rest` make up the attempt.
+pub fn suggest_keyword(rest: &str) -> Option<(&'static str, usize)> {
+    let first = rest.chars().next()?;
+    if ('\u{FF01}'..='\u{FF5E}').contains(&first) {
+        let narrow = std::char::from_u32(first as u32 - 0xFEE0)?;
+        return KEYWORDS.iter()
+            .find(|keyword| keyword.chars().eq(std::iter::once(narrow)))
+            .map(|keyword| (*keyword, first.len_utf8()));
+    }
+
+    KEYWORDS.iter()
+        .filter_map(|keyword| {
+            let matched: usize = keyword.chars()
+                .zip(rest.chars())
+                .take_while(|(expected, found)| expected == found)
+                .map(|(expected, _)| expected.len_utf8())
+                .sum();
+            if matched > 0 && matched < keyword.len() {
+                Some((*keyword, matched))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(_, matched)| *matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lexer_lexes_stack_words_in_any_case() {
+        let mut lex = Token::lexer("DUP drop Swap OVER rot NIP tuck dupe");
+
+        assert_eq!(lex.next(), Some(Token::DUP));
+        assert_eq!(lex.next(), Some(Token::DROP));
+        assert_eq!(lex.next(), Some(Token::SWAP));
+        assert_eq!(lex.next(), Some(Token::OVER));
+        assert_eq!(lex.next(), Some(Token::ROT));
+        assert_eq!(lex.next(), Some(Token::NIP));
+        assert_eq!(lex.next(), Some(Token::TUCK));
+        assert_eq!(lex.next(), Some(Token::UNKNOWNWORD));
+            assert_eq!(lex.slice(), "dupe");
+    }
+
+    #[test]
+    fn lexer_lexes_control_words() {
+        let mut lex = Token::lexer("IF else Then begin UNTIL while REPEAT Do loop i");
+
+        assert_eq!(lex.next(), Some(Token::IF));
+        assert_eq!(lex.next(), Some(Token::ELSE));
+        assert_eq!(lex.next(), Some(Token::THEN));
+        assert_eq!(lex.next(), Some(Token::BEGIN));
+        assert_eq!(lex.next(), Some(Token::UNTIL));
+        assert_eq!(lex.next(), Some(Token::WHILE));
+        assert_eq!(lex.next(), Some(Token::REPEAT));
+        assert_eq!(lex.next(), Some(Token::DO));
+        assert_eq!(lex.next(), Some(Token::LOOP));
+        assert_eq!(lex.next(), Some(Token::I));
+            assert_eq!(lex.span(), 46..47);
+    }
+
+    #[test]
+    fn lexer_lexes_word_definitions() {
+        let mut lex = Token::lexer(": 【二倍】 DUP + ; 【レムの歌】");
+
+        assert_eq!(lex.next(), Some(Token::COLON));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+            assert_eq!(lex.slice(), "【二倍】");
+        assert_eq!(lex.next(), Some(Token::DUP));
+        assert_eq!(lex.next(), Some(Token::PLUS));
+        assert_eq!(lex.next(), Some(Token::SEMICOLON));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+            assert_eq!(lex.slice(), "【レムの歌】");
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_comparisons() {
+        let mut lex = Token::lexer("= <> < > <= >= 0= 0< 0> And or INVERT");
+
+        assert_eq!(lex.next(), Some(Token::EQUAL));
+        assert_eq!(lex.next(), Some(Token::NOTEQUAL));
+        assert_eq!(lex.next(), Some(Token::LESS));
+        assert_eq!(lex.next(), Some(Token::GREATER));
+        assert_eq!(lex.next(), Some(Token::LESSEQUAL));
+        assert_eq!(lex.next(), Some(Token::GREATEREQUAL));
+        assert_eq!(lex.next(), Some(Token::ZEROEQUAL));
+        assert_eq!(lex.next(), Some(Token::ZEROLESS));
+        assert_eq!(lex.next(), Some(Token::ZEROGREATER));
+        assert_eq!(lex.next(), Some(Token::AND));
+        assert_eq!(lex.next(), Some(Token::OR));
+        assert_eq!(lex.next(), Some(Token::INVERT));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_arithmetic_words() {
+        let mut lex = Token::lexer("mod /MOD / Negate ABS min MAX lshift RSHIFT xor");
+
+        assert_eq!(lex.next(), Some(Token::MOD));
+        assert_eq!(lex.next(), Some(Token::DIVMOD));
+        assert_eq!(lex.next(), Some(Token::DIV));
+        assert_eq!(lex.next(), Some(Token::NEGATE));
+        assert_eq!(lex.next(), Some(Token::ABS));
+        assert_eq!(lex.next(), Some(Token::MIN));
+        assert_eq!(lex.next(), Some(Token::MAX));
+        assert_eq!(lex.next(), Some(Token::LSHIFT));
+        assert_eq!(lex.next(), Some(Token::RSHIFT));
+        assert_eq!(lex.next(), Some(Token::XOR));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_printing_words() {
+        let mut lex = Token::lexer(". .S ベティ .s cr EMIT F.");
+
+        assert_eq!(lex.next(), Some(Token::DOT));
+        assert_eq!(lex.next(), Some(Token::PRINTSTACK));
+        assert_eq!(lex.next(), Some(Token::PRINTSTACK));
+        assert_eq!(lex.next(), Some(Token::PRINTSTACK));
+        assert_eq!(lex.next(), Some(Token::CR));
+        assert_eq!(lex.next(), Some(Token::EMIT));
+        assert_eq!(lex.next(), Some(Token::FPRINT));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_bye() {
+        let mut lex = Token::lexer("レムラム BYE bye");
+
+        assert_eq!(lex.next(), Some(Token::ONE));
+        assert_eq!(lex.next(), Some(Token::FINALIZER));
+        assert_eq!(lex.next(), Some(Token::BYE));
+        assert_eq!(lex.next(), Some(Token::BYE));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_include() {
+        let mut lex = Token::lexer("INCLUDE include");
+
+        assert_eq!(lex.next(), Some(Token::INCLUDE));
+        assert_eq!(lex.next(), Some(Token::INCLUDE));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_input_words() {
+        let mut lex = Token::lexer("KEY accept Read-Number エミリアラム BUFFER: 【行】");
+
+        assert_eq!(lex.next(), Some(Token::KEY));
+        assert_eq!(lex.next(), Some(Token::ACCEPT));
+        assert_eq!(lex.next(), Some(Token::READNUMBER));
+        assert_eq!(lex.next(), Some(Token::TEN));
+        assert_eq!(lex.next(), Some(Token::FINALIZER));
+        assert_eq!(lex.next(), Some(Token::BUFFER));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_argument_words() {
+        let mut lex = Token::lexer("ARGC arg Arg>Number ENV スバルHOME君");
+
+        assert_eq!(lex.next(), Some(Token::ARGC));
+        assert_eq!(lex.next(), Some(Token::ARG));
+        assert_eq!(lex.next(), Some(Token::ARGNUMBER));
+        assert_eq!(lex.next(), Some(Token::ENV));
+        assert_eq!(lex.next(), Some(Token::STRINGSTART));
+    }
+
+    #[test]
+    fn lexer_lexes_floats() {
+        let mut lex = Token::lexer("レム・レムラム S>F f+ F- F* f/ FNEGATE F. fdup FDROP FSwap FOVER F>S");
+
+        assert_eq!(lex.next(), Some(Token::ONE));
+        assert_eq!(lex.next(), Some(Token::POINT));
+        assert_eq!(lex.next(), Some(Token::ONE));
+        assert_eq!(lex.next(), Some(Token::FINALIZER));
+        assert_eq!(lex.next(), Some(Token::CELLTOFLOAT));
+        assert_eq!(lex.next(), Some(Token::FPLUS));
+        assert_eq!(lex.next(), Some(Token::FMINUS));
+        assert_eq!(lex.next(), Some(Token::FMULT));
+        assert_eq!(lex.next(), Some(Token::FDIV));
+        assert_eq!(lex.next(), Some(Token::FNEGATE));
+        assert_eq!(lex.next(), Some(Token::FPRINT));
+        assert_eq!(lex.next(), Some(Token::FDUP));
+        assert_eq!(lex.next(), Some(Token::FDROP));
+        assert_eq!(lex.next(), Some(Token::FSWAP));
+        assert_eq!(lex.next(), Some(Token::FOVER));
+        assert_eq!(lex.next(), Some(Token::FLOATTOCELL));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_variables() {
+        let mut lex = Token::lexer("Variable 【数】 【数】 ! 【数】 @");
+
+        assert_eq!(lex.next(), Some(Token::VARIABLE));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+        assert_eq!(lex.next(), Some(Token::STORE));
+        assert_eq!(lex.next(), Some(Token::WORDNAME));
+        assert_eq!(lex.next(), Some(Token::FETCH));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn lexer_lexes_compact_numbers() {
+        let mut lex = Token::lexer("レムロズワールパックエミリアラム");
+
+        assert_eq!(lex.next(), Some(Token::ONE));
+        assert_eq!(lex.next(), Some(Token::THOUSAND));
+            assert_eq!(lex.span(), 6..21);
+        assert_eq!(lex.next(), Some(Token::HUNDRED));
+            assert_eq!(lex.span(), 21..30);
+        assert_eq!(lex.next(), Some(Token::TEN));
+            assert_eq!(lex.span(), 30..42);
+        assert_eq!(lex.next(), Some(Token::FINALIZER));
+    }
+
+    #[test]
+    fn lexer_skips_comments_and_keeps_their_spans() {
+        let mut lex = Token::lexer("レム※ベティ\nラム（スバル（君）+）ベティ");
+
+        assert_eq!(lex.next(), Some(Token::ONE));
+            assert_eq!(lex.span(), 0..6);
+
+        assert_eq!(lex.next(), Some(Token::FINALIZER));
+            assert_eq!(lex.span(), 19..25);
+            assert_eq!(lex.slice(), "ラム");
+
+        assert_eq!(lex.next(), Some(Token::PRINTSTACK));
+            assert_eq!(lex.span(), 50..59);
+            assert_eq!(lex.slice(), "ベティ");
+
+        assert_eq!(lex.next(), None);
+        assert_eq!(lex.extras, vec![6..18, 25..50]);
+    }
+
+    #[test]
+    fn lexer_reports_an_unterminated_block_comment() {
+        let mut lex = Token::lexer("レム（（）ラム");
+
+        assert_eq!(lex.next(), Some(Token::ONE));
+        assert_eq!(lex.next(), Some(Token::UNTERMINATEDCOMMENT));
+            assert_eq!(lex.span(), 6..21);
+        assert_eq!(lex.next(), None);
+        assert!(lex.extras.is_empty());
+    }
+
+    #[test]
+    fn string_lexer_lexes_unicode_text_and_escapes() {
+        let mut lex = StringPart::lexer("こんにちは, world 42!\\n\\t\\君\\\\君");
+
+        assert_eq!(lex.next(), Some(StringPart::STRINGLITERAL));
+            assert_eq!(lex.slice(), "こんにちは, world 42!");
+        assert_eq!(lex.next(), Some(StringPart::NEWLINE));
+        assert_eq!(lex.next(), Some(StringPart::TAB));
+        assert_eq!(lex.next(), Some(StringPart::ESCAPEDEND));
+        assert_eq!(lex.next(), Some(StringPart::BACKSLASH));
+        assert_eq!(lex.next(), Some(StringPart::STRINGEND));
+        assert_eq!(lex.next(), None);
+    }
+
+    #[test]
+    fn suggest_keyword_finishes_half_written_keywords() {
+        assert_eq!(suggest_keyword("レ+"), Some(("レム", 3)));
+        assert_eq!(suggest_keyword("ラ"), Some(("ラム", 3)));
+        assert_eq!(suggest_keyword("スバ君"), Some(("スバル", 6)));
+        assert_eq!(suggest_keyword("ベテレム"), Some(("ベティ", 6)));
+    }
+
+    #[test]
+    fn suggest_keyword_narrows_full_width_operators() {
+        assert_eq!(suggest_keyword("＋"), Some(("+", 3)));
+        assert_eq!(suggest_keyword("＊レム"), Some(("*", 3)));
+    }
+
+    #[test]
+    fn suggest_keyword_gives_up_on_unrelated_characters() {
+        assert_eq!(suggest_keyword("ム"), None);
+        assert_eq!(suggest_keyword("＃"), None);
+        assert_eq!(suggest_keyword("!"), None);
+    }
+}